@@ -1,8 +1,9 @@
-use crate::comms::{CommsMessage, CommsVerifier};
+use crate::comms::{CommsMessage, CommsVerifier, Shutdown};
 use crate::manager::AccountStatus;
 use crate::primitives::{Account, AccountType, ChallengeStatus, NetAccount, Result};
 use crate::Database;
 use strsim::jaro;
+use unicode_normalization::UnicodeNormalization;
 
 pub const VIOLATIONS_CAP: usize = 5;
 
@@ -20,18 +21,24 @@ impl DisplayNameHandler {
             limit: limit,
         }
     }
-    pub async fn start(self) {
+    pub async fn start(self, shutdown: Shutdown) {
+        let mut shutdown = shutdown.subscribe();
         loop {
-            let _ = self.local().await.map_err(|err| {
+            let msg = tokio::select! {
+                msg = self.comms.recv() => msg,
+                _ = shutdown.recv() => break,
+            };
+
+            let _ = self.local(msg).await.map_err(|err| {
                 error!("{}", err);
                 err
             });
         }
     }
-    pub async fn local(&self) -> Result<()> {
+    pub async fn local(&self, msg: CommsMessage) -> Result<()> {
         use CommsMessage::*;
 
-        match self.comms.recv().await {
+        match msg {
             AccountToVerify {
                 net_account,
                 account,
@@ -106,8 +113,8 @@ impl DisplayNameHandler {
         Ok(())
     }
     fn is_too_similar(display_name: &Account, account: &Account, limit: f64) -> bool {
-        let name_str = display_name.as_str().to_lowercase();
-        let account_str = account.as_str().to_lowercase();
+        let name_str = normalize_display_name(display_name.as_str());
+        let account_str = normalize_display_name(account.as_str());
 
         let similarities = [
             jaro(&name_str, &account_str),
@@ -118,6 +125,12 @@ impl DisplayNameHandler {
     }
 }
 
+/// Applies Unicode NFC normalization and lowercases the display name, so
+/// precomposed and decomposed characters compare as equal.
+pub fn normalize_display_name(display_name: &str) -> String {
+    display_name.nfc().collect::<String>().to_lowercase()
+}
+
 fn jaro_words(left: &str, right: &str, delimiter: &[&str]) -> f64 {
     fn splitter<'a>(string: &'a str, delimiter: &[&str]) -> Vec<&'a str> {
         let mut all = vec![];
@@ -295,6 +308,49 @@ mod tests {
         }
     }
 
+    #[test]
+    fn normalize_display_name_nfc() {
+        // Precomposed "é" (U+00E9) and decomposed "e" + U+0301.
+        let precomposed = "Jos\u{e9}";
+        let decomposed = "Jose\u{301}";
+        assert_ne!(precomposed, decomposed);
+
+        assert_eq!(
+            normalize_display_name(precomposed),
+            normalize_display_name(decomposed)
+        );
+        assert_eq!(normalize_display_name(decomposed), "jos\u{e9}");
+        assert_eq!(normalize_display_name("ÅNGSTRÖM"), "ångström");
+    }
+
+    #[test]
+    fn is_too_similar_normalized() {
+        let current = [
+            Account::from("Jose\u{301} Mari\u{301}a"),
+            Account::from("JOSE\u{301} MARI\u{301}A"),
+            Account::from("Zo\u{308}e"),
+        ];
+
+        let new = [
+            Account::from("Jos\u{e9} Mar\u{ed}a"),
+            Account::from("Jos\u{e9} Mar\u{ed}a"),
+            Account::from("Z\u{f6}e"),
+        ];
+
+        for (account, new) in current.iter().zip(new.iter()) {
+            let res = DisplayNameHandler::is_too_similar(account, new, LIMIT);
+            assert!(res);
+        }
+
+        let current = [Account::from("Jose\u{301} Mari\u{301}a")];
+        let new = Account::from("Zo\u{308}e");
+
+        for account in &current {
+            let res = DisplayNameHandler::is_too_similar(account, &new, LIMIT);
+            assert!(!res);
+        }
+    }
+
     #[test]
     fn is_too_similar_unicode() {
         let current = [Account::from("👻🥺👌 Alice")];
@@ -1,13 +1,16 @@
-use crate::comms::{CommsMessage, CommsVerifier};
+use crate::adapters::DEFAULT_ADAPTER_TIMEOUT;
+use crate::comms::{CommsMessage, CommsVerifier, Shutdown};
 use crate::db::Database;
 use crate::manager::AccountStatus;
 use crate::primitives::{Account, AccountType, NetAccount, Result};
-use crate::verifier::{invalid_accounts_message, verification_handler, Verifier, VerifierMessage};
+use crate::verifier::{invalid_accounts_message, verification_response, Verifier, VerifierMessage};
 use lettre::smtp::authentication::Credentials;
 use lettre::smtp::SmtpClient;
 use lettre::Transport;
 use lettre_email::EmailBuilder;
 use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
 use std::result::Result as StdResult;
 use tokio::time::{self, Duration};
 
@@ -94,6 +97,7 @@ pub struct SmtpImapClientBuilder {
     inbox: Option<String>,
     user: Option<String>,
     password: Option<String>,
+    timeout: Option<Duration>,
 }
 
 impl SmtpImapClientBuilder {
@@ -104,6 +108,7 @@ impl SmtpImapClientBuilder {
             inbox: None,
             user: None,
             password: None,
+            timeout: None,
         }
     }
     pub fn email_server(mut self, server: String) -> Self {
@@ -126,6 +131,12 @@ impl SmtpImapClientBuilder {
         self.password = Some(password);
         self
     }
+    /// Read/write timeout of the SMTP and IMAP connections. Defaults to
+    /// `DEFAULT_ADAPTER_TIMEOUT`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
     pub fn build(self) -> Result<SmtpImapClient> {
         Ok(SmtpImapClient {
             smtp_server: self.server.ok_or(ClientError::IncompleteBuilder)?,
@@ -133,6 +144,7 @@ impl SmtpImapClientBuilder {
             inbox: self.inbox.ok_or(ClientError::IncompleteBuilder)?,
             user: self.user.ok_or(ClientError::IncompleteBuilder)?,
             password: self.password.ok_or(ClientError::IncompleteBuilder)?,
+            timeout: self.timeout.unwrap_or(DEFAULT_ADAPTER_TIMEOUT),
         })
     }
 }
@@ -143,6 +155,32 @@ pub trait EmailTransport: 'static + Send + Sync {
     async fn send_message(&self, account: &Account, msg: VerifierMessage) -> Result<()>;
 }
 
+/// Port of the IMAP server (IMAP over TLS).
+const IMAP_PORT: u16 = 993;
+/// Port of the SMTP server, as used by `SmtpClient::new_simple`.
+const SMTP_PORT: u16 = 465;
+
+/// Opens a TCP connection to the host, trying each of its resolved addresses.
+/// Unlike `TcpStream::connect`, each attempt is aborted after `timeout`
+/// instead of waiting for the operating system to give up, which can take
+/// minutes.
+fn connect_timeout(host: &str, port: u16, timeout: Duration) -> io::Result<TcpStream> {
+    let mut last_err = None;
+    for addr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(tcp) => return Ok(tcp),
+            Err(err) => last_err = Some(err),
+        }
+    }
+
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("could not resolve address of {}", host),
+        )
+    }))
+}
+
 #[derive(Clone)]
 pub struct SmtpImapClient {
     smtp_server: String,
@@ -150,13 +188,22 @@ pub struct SmtpImapClient {
     inbox: String,
     user: String,
     password: String,
+    // The `imap` and `lettre` clients are blocking, so the timeout is applied
+    // to the underlying sockets rather than with `tokio::time::timeout`.
+    timeout: Duration,
 }
 
 #[async_trait]
 impl EmailTransport for SmtpImapClient {
     async fn request_messages(&self) -> Result<Vec<ReceivedMessageContext>> {
         let tls = native_tls::TlsConnector::builder().build()?;
-        let client = imap::connect((self.imap_server.as_str(), 993), &self.imap_server, &tls)?;
+        let tcp = connect_timeout(&self.imap_server, IMAP_PORT, self.timeout)?;
+        tcp.set_read_timeout(Some(self.timeout))?;
+        tcp.set_write_timeout(Some(self.timeout))?;
+        let tls_stream = tls.connect(&self.imap_server, tcp)?;
+
+        let mut client = imap::Client::new(tls_stream);
+        client.read_greeting()?;
 
         let mut imap = client
             .login(&self.user, &self.password)
@@ -253,8 +300,14 @@ impl EmailTransport for SmtpImapClient {
         Ok(parsed_messages)
     }
     async fn send_message(&self, account: &Account, message: VerifierMessage) -> Result<()> {
+        // lettre connects without a timeout, so an unreachable server would
+        // block the adapter until the operating system gives up. Check that
+        // the server is reachable first.
+        drop(connect_timeout(&self.smtp_server, SMTP_PORT, self.timeout)?);
+
         // SMTP transport
         let mut smtp = SmtpClient::new_simple(&self.smtp_server)?
+            .timeout(Some(self.timeout))
             .credentials(Credentials::new(
                 self.user.to_string(),
                 self.password.to_string(),
@@ -292,11 +345,12 @@ impl EmailHandler {
 }
 
 impl EmailHandler {
-    pub async fn start<T: Clone + EmailTransport>(self, transport: T) {
+    pub async fn start<T: Clone + EmailTransport>(self, transport: T, shutdown: Shutdown) {
         // Start incoming messages handler.
         let l_self = self.clone();
         let l_transport = transport.clone();
-        tokio::spawn(async move {
+        let mut l_shutdown = shutdown.subscribe();
+        let incoming = tokio::spawn(async move {
             loop {
                 let _ = l_self
                     .handle_incoming_messages(&l_transport)
@@ -306,21 +360,32 @@ impl EmailHandler {
                         err
                     });
 
-                time::delay_for(Duration::from_secs(3)).await;
+                tokio::select! {
+                    _ = time::delay_for(Duration::from_secs(3)) => {}
+                    _ = l_shutdown.recv() => break,
+                }
             }
         });
 
+        let mut shutdown = shutdown.subscribe();
         loop {
-            let _ = self.local(&transport).await.map_err(|err| {
+            let msg = tokio::select! {
+                msg = self.comms.recv() => msg,
+                _ = shutdown.recv() => break,
+            };
+
+            let _ = self.local(&transport, msg).await.map_err(|err| {
                 error!("{}", err);
                 err
             });
         }
+
+        let _ = incoming.await;
     }
-    async fn local<T: EmailTransport>(&self, transport: &T) -> Result<()> {
+    async fn local<T: EmailTransport>(&self, transport: &T, msg: CommsMessage) -> Result<()> {
         use CommsMessage::*;
 
-        match self.comms.recv().await {
+        match msg {
             AccountToVerify {
                 net_account: _,
                 account,
@@ -415,12 +480,12 @@ impl EmailHandler {
             }
 
             // Update challenge statuses and notify manager
-            verification_handler(&verifier, &self.db, &self.comms, &AccountType::Email).await?;
+            let response =
+                verification_response(&verifier, &self.db, &self.comms, &AccountType::Email)
+                    .await?;
 
             // Inform user about the current state of the verification
-            transport
-                .send_message(sender, verifier.response_message_builder())
-                .await?;
+            transport.send_message(sender, response).await?;
 
             self.db.track_email_id(email_id).await?;
         }
@@ -1,8 +1,10 @@
-use crate::comms::{CommsMessage, CommsVerifier};
+use crate::adapters::rate_limiter::RateLimiter;
+use crate::adapters::with_timeout;
+use crate::comms::{CommsMessage, CommsVerifier, Shutdown};
 use crate::db::Database;
 use crate::manager::AccountStatus;
 use crate::primitives::{Account, AccountType, NetAccount, Result};
-use crate::verifier::{invalid_accounts_message, verification_handler, Verifier, VerifierMessage};
+use crate::verifier::{invalid_accounts_message, verification_response, Verifier, VerifierMessage};
 use matrix_sdk::{
     self,
     api::r0::room::create_room::{Request, Response},
@@ -14,11 +16,19 @@ use matrix_sdk::{
     identifiers::{RoomId, UserId},
     Client, ClientConfig, EventEmitter, JsonStore, SyncRoom, SyncSettings,
 };
+use pulldown_cmark::{html, Event, Parser};
 use std::convert::TryInto;
 use std::result::Result as StdResult;
 use tokio::time::{self, Duration};
 use url::Url;
 
+/// The maximum amount of messages which can be sent to the homeserver in a
+/// single burst, before the rate limit kicks in.
+const MAX_MESSAGES_BURST: u32 = 5;
+/// Interval (in seconds) in which the validity of the Matrix session is
+/// checked.
+const SESSION_CHECK_INTERVAL: u64 = 300;
+
 #[derive(Debug, Fail)]
 pub enum MatrixError {
     #[fail(display = "failed to open state store: {}", 0)]
@@ -61,6 +71,8 @@ pub trait MatrixTransport: 'static + Send + Sync {
 #[derive(Clone)]
 pub struct MatrixClient {
     client: Client, // `Client` from matrix_sdk
+    rate_limiter: RateLimiter,
+    timeout: Duration,
 }
 
 impl MatrixClient {
@@ -70,6 +82,8 @@ impl MatrixClient {
         password: &str,
         db_path: &str,
         db: Database,
+        max_messages_per_second: f32,
+        timeout: Duration,
     ) -> Result<MatrixClient> {
         info!("Setting up Matrix client");
         // Setup client
@@ -77,24 +91,79 @@ impl MatrixClient {
         let client_config = ClientConfig::new().state_store(Box::new(store));
 
         let homeserver = Url::parse(homeserver).expect("Couldn't parse the homeserver URL");
-        let client = Client::new_with_config(homeserver, client_config)
+        let client = Client::new_with_config(homeserver.clone(), client_config)
             .map_err(|err| MatrixError::ClientCreation(err.into()))?;
 
         // Login with credentials
-        client
-            .login(username, password, None, Some("w3f-registrar-bot"))
-            .await
-            .map_err(|err| MatrixError::Login(err.into()))?;
-
-        // Sync up, avoid responding to old messages.
+        let login = with_timeout(
+            AccountType::Matrix,
+            "login",
+            timeout,
+            client.login(username, password, None, Some("w3f-registrar-bot")),
+        )
+        .await?
+        .map_err(|err| MatrixError::Login(err.into()))?;
+
+        // Sync up, avoid responding to old messages. Not subject to the
+        // timeout, since the initial sync of an account with many rooms can
+        // easily take several minutes.
         info!("Syncing Matrix client");
         client
             .sync(SyncSettings::default())
             .await
             .map_err(|err| MatrixError::Sync(err.into()))?;
 
-        // Request a list of open/pending room ids. Used to detect dead rooms.
-        let pending_room_ids = db.select_room_ids().await?;
+        // Leave the rooms of identities which no longer exist, then forget
+        // about them. Rooms which could not be left are retried on the next
+        // start.
+        let joined_room_ids = {
+            let rooms = client.joined_rooms();
+            let rooms = rooms.read().await;
+            rooms.keys().cloned().collect::<Vec<RoomId>>()
+        };
+
+        let mut orphaned_count = 0;
+        for room_id in db.select_orphaned_room_ids().await? {
+            if joined_room_ids.contains(&room_id) {
+                let res: Result<()> = match with_timeout(
+                    AccountType::Matrix,
+                    "leave room",
+                    timeout,
+                    client.leave_room(&room_id),
+                )
+                .await
+                {
+                    Ok(res) => res.map(|_| ()).map_err(|err| err.into()),
+                    Err(err) => Err(err.into()),
+                };
+
+                if let Err(err) = res {
+                    warn!(
+                        "Failed to leave orphaned room {}: {}",
+                        room_id.as_str(),
+                        err
+                    );
+                    continue;
+                }
+            }
+
+            db.delete_room_id(&room_id).await?;
+            orphaned_count += 1;
+        }
+
+        if orphaned_count > 0 {
+            info!("Removed {} orphaned Matrix room(s)", orphaned_count);
+        }
+
+        // Request a list of rooms which belong to a pending judgement. Used to
+        // detect dead rooms.
+        let pending_room_ids = db
+            .select_rooms()
+            .await?
+            .into_iter()
+            .filter(|(_, net_account)| net_account.is_some())
+            .map(|(room_id, _)| room_id)
+            .collect::<Vec<_>>();
 
         // Leave dead rooms.
         info!("Detecting dead Matrix rooms");
@@ -115,40 +184,166 @@ impl MatrixClient {
                 .await;
         });
 
-        let matrix = MatrixClient { client: client };
+        // Re-authenticate if the homeserver revokes the session.
+        let watchdog_client = client.clone();
+        let username = username.to_string();
+        let password = password.to_string();
+        tokio::spawn(async move {
+            Self::session_watchdog(
+                watchdog_client,
+                homeserver,
+                username,
+                password,
+                login.access_token,
+                timeout,
+            )
+            .await;
+        });
+
+        let matrix = MatrixClient {
+            client: client,
+            rate_limiter: RateLimiter::new(MAX_MESSAGES_BURST, max_messages_per_second as f64),
+            timeout: timeout,
+        };
 
         Ok(matrix)
     }
-}
-
-#[async_trait]
-impl MatrixTransport for MatrixClient {
-    async fn send_message(&self, room_id: &RoomId, message: VerifierMessage) -> Result<()> {
-        self.client
-            .room_send(
+    /// Sends the plain text message together with an HTML version, rendered
+    /// via Markdown. Clients without HTML support display the unchanged plain
+    /// text.
+    pub async fn send_formatted_message(&self, room_id: &RoomId, text: &str) -> Result<()> {
+        // Avoid getting throttled or banned by the homeserver.
+        self.rate_limiter.acquire().await;
+
+        with_timeout(
+            AccountType::Matrix,
+            "send message",
+            self.timeout,
+            self.client.room_send(
                 room_id,
                 AnyMessageEventContent::RoomMessage(MessageEventContent::Text(
-                    // TODO: Make a proper Message Creator for this
-                    TextMessageEventContent::plain(message.as_str()),
+                    TextMessageEventContent::html(text, markdown_to_html(&to_markdown(text))),
                 )),
                 None,
+            ),
+        )
+        .await?
+        .map_err(|err| err.into())
+        .map(|_| ())
+    }
+    async fn session_watchdog(
+        client: Client,
+        homeserver: Url,
+        username: String,
+        password: String,
+        mut access_token: String,
+        timeout: Duration,
+    ) {
+        let url = homeserver
+            .join("_matrix/client/r0/account/whoami")
+            .expect("Couldn't create the whoami URL");
+        let http = reqwest::Client::new();
+
+        let mut interval = time::interval(Duration::from_secs(SESSION_CHECK_INTERVAL));
+        loop {
+            interval.tick().await;
+
+            if check_session(&http, &url, &access_token, timeout).await != SessionStatus::Revoked {
+                continue;
+            }
+
+            warn!("Matrix session was revoked by the homeserver, re-authenticating");
+            match with_timeout(
+                AccountType::Matrix,
+                "login",
+                timeout,
+                client.login(
+                    username.as_str(),
+                    password.as_str(),
+                    None,
+                    Some("w3f-registrar-bot"),
+                ),
             )
             .await
-            .map_err(|err| err.into())
-            .map(|_| ())
+            {
+                Ok(Ok(login)) => access_token = login.access_token,
+                Ok(Err(err)) => error!("{}", MatrixError::Login(err.into())),
+                Err(err) => error!("{}", MatrixError::Login(err.into())),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum SessionStatus {
+    Valid,
+    Revoked,
+    Unknown,
+}
+
+/// Checks the access token against the `whoami` endpoint. Only an explicit
+/// `401 Unauthorized` counts as revoked; transport errors and other status
+/// codes leave the session as it is.
+async fn check_session(
+    http: &reqwest::Client,
+    url: &Url,
+    access_token: &str,
+    timeout: Duration,
+) -> SessionStatus {
+    match with_timeout(
+        AccountType::Matrix,
+        "session check",
+        timeout,
+        http.get(url.clone()).bearer_auth(access_token).send(),
+    )
+    .await
+    {
+        Ok(Ok(resp)) => {
+            if resp.status() == reqwest::StatusCode::UNAUTHORIZED {
+                SessionStatus::Revoked
+            } else if resp.status().is_success() {
+                SessionStatus::Valid
+            } else {
+                warn!("Unexpected Matrix session check status: {}", resp.status());
+                SessionStatus::Unknown
+            }
+        }
+        Ok(Err(err)) => {
+            warn!("Failed to check the Matrix session: {}", err);
+            SessionStatus::Unknown
+        }
+        Err(err) => {
+            warn!("Failed to check the Matrix session: {}", err);
+            SessionStatus::Unknown
+        }
+    }
+}
+
+#[async_trait]
+impl MatrixTransport for MatrixClient {
+    async fn send_message(&self, room_id: &RoomId, message: VerifierMessage) -> Result<()> {
+        self.send_formatted_message(room_id, message.as_str()).await
     }
     async fn create_room<'a>(&'a self, request: Request<'a>) -> Result<Response> {
-        self.client
-            .create_room(request)
-            .await
-            .map_err(|err| err.into())
+        with_timeout(
+            AccountType::Matrix,
+            "create room",
+            self.timeout,
+            self.client.create_room(request),
+        )
+        .await?
+        .map_err(|err| err.into())
     }
     async fn leave_room(&self, room_id: &RoomId) -> Result<()> {
-        self.client
-            .leave_room(room_id)
-            .await
-            .map_err(|err| err.into())
-            .map(|_| ())
+        with_timeout(
+            AccountType::Matrix,
+            "leave room",
+            self.timeout,
+            self.client.leave_room(room_id),
+        )
+        .await?
+        .map_err(|err| err.into())
+        .map(|_| ())
     }
     async fn user_id(&self) -> Result<UserId> {
         //self.client.user_id().await.ok_or(failure::Error::from(Err(MatrixError::RemoteUserIdNotFound)))
@@ -177,12 +372,78 @@ impl EventExtract for SyncMessageEvent<MessageEventContent> {
             SyncMessageEvent {
                 content: MessageEventContent::Text(TextMessageEventContent { body, .. }),
                 ..
-            } => Ok(body.to_owned()),
+            } => Ok(strip_reply_fallback(body)),
             _ => Err(failure::err_msg("not a string body")),
         }
     }
 }
 
+/// Converts the plain text messages created by the verifier into Markdown.
+/// Quoted values (addresses, challenges, etc.) are displayed as inline code,
+/// quoted URLs as links, and line breaks are preserved.
+fn to_markdown(message: &str) -> String {
+    message
+        .lines()
+        .map(|line| match line.strip_prefix("> ") {
+            Some(value) if is_url(value) => format!("<{}>", value),
+            Some(value) => code_span(value),
+            None => line.to_string(),
+        })
+        .collect::<Vec<String>>()
+        .join("  \n")
+}
+
+fn is_url(value: &str) -> bool {
+    (value.starts_with("https://") || value.starts_with("http://"))
+        && !value.contains(|c: char| c.is_whitespace() || c == '<' || c == '>')
+}
+
+/// Wraps the value in a code span. The delimiter is longer than any run of
+/// backticks within the value, so the value cannot terminate the span.
+fn code_span(value: &str) -> String {
+    let longest_run = value
+        .split(|c| c != '`')
+        .map(|run| run.len())
+        .max()
+        .unwrap_or(0);
+    let delimiter = "`".repeat(longest_run + 1);
+
+    if longest_run > 0 {
+        format!("{} {} {}", delimiter, value, delimiter)
+    } else {
+        format!("{}{}{}", delimiter, value, delimiter)
+    }
+}
+
+/// Renders the Markdown to HTML. Messages contain values controlled by other
+/// users (e.g. on-chain display names), so raw HTML is escaped and displayed
+/// as text instead of being passed through.
+fn markdown_to_html(markdown: &str) -> String {
+    let events = Parser::new(markdown).map(|event| match event {
+        Event::Html(html) => Event::Text(html),
+        event => event,
+    });
+
+    let mut output = String::new();
+    html::push_html(&mut output, events);
+    output
+}
+
+/// Removes the reply fallback from the plain text body of a Matrix message.
+/// Clients which reply to a message prepend the quoted original message
+/// (lines beginning with `>`), followed by an empty line.
+fn strip_reply_fallback(body: &str) -> String {
+    if !body.starts_with('>') {
+        return body.to_string();
+    }
+
+    body.lines()
+        .skip_while(|line| line.starts_with('>'))
+        .skip_while(|line| line.trim().is_empty())
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
 pub struct MatrixHandler {
     db: Database,
     comms: CommsVerifier,
@@ -201,18 +462,24 @@ impl MatrixHandler {
             transport: Box::new(transport),
         }
     }
-    pub async fn start(self) {
+    pub async fn start(self, shutdown: Shutdown) {
+        let mut shutdown = shutdown.subscribe();
         loop {
-            let _ = self.local().await.map_err(|err| {
+            let msg = tokio::select! {
+                msg = self.comms.recv() => msg,
+                _ = shutdown.recv() => break,
+            };
+
+            let _ = self.local(msg).await.map_err(|err| {
                 error!("{}", err);
                 err
             });
         }
     }
-    async fn local(&self) -> Result<()> {
+    async fn local(&self, msg: CommsMessage) -> Result<()> {
         use CommsMessage::*;
 
-        match self.comms.recv().await {
+        match msg {
             AccountToVerify {
                 net_account,
                 account,
@@ -368,11 +635,18 @@ impl MatrixHandler {
             let account = Account::from(event.sender().as_str());
 
             debug!("Fetching challenge data");
-            let (challenge_data, _) = self
+            let (mut challenge_data, _) = self
                 .db
                 .select_challenge_data(&account, &AccountType::Matrix)
                 .await?;
 
+            // If the room belongs to a pending judgement, only verify the
+            // challenge of that address. The same Matrix account might be
+            // used by multiple identities, each with its own room.
+            if let Some(net_account) = self.db.select_net_account_by_room_id(room_id).await? {
+                challenge_data.retain(|(address, _)| address.address() == &net_account);
+            }
+
             if challenge_data.is_empty() {
                 warn!("No challenge data found for {}", account.as_str());
                 return Err(MatrixError::ChallengeDataNotFound(account.clone()).into());
@@ -406,11 +680,13 @@ impl MatrixHandler {
             verifier.verify(&msg_body);
 
             // Update challenge statuses and notify manager
-            verification_handler(&verifier, &self.db, &self.comms, &AccountType::Matrix).await?;
+            let response =
+                verification_response(&verifier, &self.db, &self.comms, &AccountType::Matrix)
+                    .await?;
 
             // Inform user about the current state of the verification
             self.transport
-                .send_message(room_id, verifier.response_message_builder())
+                .send_message(room_id, response)
                 .await
                 .map_err(|err| MatrixError::SendMessage(err.into()))?;
         } else {
@@ -482,3 +758,159 @@ impl EventEmitter for MatrixHandler {
             });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::runtime::Runtime;
+
+    /// Answers a single request with the given status line, after checking
+    /// that the access token was passed on.
+    async fn serve_status(status: &'static str) -> Url {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            let mut request = vec![];
+            let mut buffer = [0; 1024];
+            while !String::from_utf8_lossy(&request).contains("\r\n\r\n") {
+                let n = stream.read(&mut buffer).await.unwrap();
+                if n == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buffer[..n]);
+            }
+
+            let request = String::from_utf8_lossy(&request).to_lowercase();
+            assert!(request.contains("authorization: bearer secret_token"));
+
+            let response = format!(
+                "HTTP/1.1 {}\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{{}}",
+                status
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+        });
+
+        Url::parse(&format!("http://{}/_matrix/client/r0/account/whoami", addr)).unwrap()
+    }
+
+    #[test]
+    fn check_session_status() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let http = reqwest::Client::new();
+            let timeout = Duration::from_secs(5);
+
+            let url = serve_status("200 OK").await;
+            let status = check_session(&http, &url, "secret_token", timeout).await;
+            assert_eq!(status, SessionStatus::Valid);
+
+            let url = serve_status("401 Unauthorized").await;
+            let status = check_session(&http, &url, "secret_token", timeout).await;
+            assert_eq!(status, SessionStatus::Revoked);
+
+            // Server errors do not trigger a re-authentication.
+            let url = serve_status("502 Bad Gateway").await;
+            let status = check_session(&http, &url, "secret_token", timeout).await;
+            assert_eq!(status, SessionStatus::Unknown);
+        });
+    }
+
+    #[test]
+    fn check_session_transport_error() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let http = reqwest::Client::new();
+
+            // Nothing listens on the port once the listener is dropped.
+            let addr = TcpListener::bind("127.0.0.1:0")
+                .await
+                .unwrap()
+                .local_addr()
+                .unwrap();
+            let url =
+                Url::parse(&format!("http://{}/_matrix/client/r0/account/whoami", addr)).unwrap();
+
+            let status = check_session(&http, &url, "secret_token", Duration::from_secs(5)).await;
+            assert_eq!(status, SessionStatus::Unknown);
+        });
+    }
+
+    #[test]
+    fn render_markdown() {
+        let message = "Please sign the challenge:\n\nADDRESS:\n> 14GcE3qB\nCHALLENGE:\n> 0101";
+
+        let markdown = to_markdown(message);
+        assert_eq!(
+            markdown,
+            "Please sign the challenge:  \n  \nADDRESS:  \n`14GcE3qB`  \nCHALLENGE:  \n`0101`"
+        );
+
+        assert_eq!(
+            markdown_to_html(&markdown),
+            "<p>Please sign the challenge:</p>\n\
+             <p>ADDRESS:<br />\n<code>14GcE3qB</code><br />\nCHALLENGE:<br />\n<code>0101</code></p>\n"
+        );
+    }
+
+    #[test]
+    fn render_markdown_escapes_html() {
+        let message =
+            "Similar display names:\n  * \"<a href=\"https://evil.com\">Alice</a>\"\n\n<img src=x>";
+
+        let html = markdown_to_html(&to_markdown(message));
+        assert!(!html.contains("<a "));
+        assert!(!html.contains("<img"));
+        assert!(html.contains("&lt;a href=&quot;https://evil.com&quot;&gt;Alice&lt;/a&gt;"));
+        assert!(html.contains("&lt;img src=x&gt;"));
+    }
+
+    #[test]
+    fn render_markdown_quoted_values() {
+        // Backticks within values do not break out of the code span.
+        assert_eq!(to_markdown("> a`b"), "`` a`b ``");
+        assert_eq!(to_markdown("> a``b`"), "``` a``b` ```");
+        assert_eq!(
+            markdown_to_html(&to_markdown("> a`b")),
+            "<p><code>a`b</code></p>\n"
+        );
+
+        // URLs stay clickable.
+        let message = "SIGN WITH POLKADOT JS APPS:\n> https://polkadot.js.org/apps/?rpc=wss%3A%2F%2Frpc.polkadot.io#/signing";
+        assert_eq!(
+            markdown_to_html(&to_markdown(message)),
+            "<p>SIGN WITH POLKADOT JS APPS:<br />\n\
+             <a href=\"https://polkadot.js.org/apps/?rpc=wss%3A%2F%2Frpc.polkadot.io#/signing\">\
+             https://polkadot.js.org/apps/?rpc=wss%3A%2F%2Frpc.polkadot.io#/signing</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn strip_reply_fallback_from_body() {
+        // Plain message.
+        let body = "Sig: 0x1234";
+        assert_eq!(strip_reply_fallback(body), "Sig: 0x1234");
+
+        // Reply to a single line message.
+        let body = "> <@registrar:matrix.org> Please sign the challenge\n\nSig: 0x1234";
+        assert_eq!(strip_reply_fallback(body), "Sig: 0x1234");
+
+        // Reply to a multi line message, with a multi line response.
+        let body = "> <@registrar:matrix.org> Please sign the challenge\n\
+                    > 0xabcd\n\
+                    >\n\
+                    > Thanks!\n\
+                    \n\
+                    Sig: 0x1234\n\
+                    > not a fallback";
+        assert_eq!(strip_reply_fallback(body), "Sig: 0x1234\n> not a fallback");
+
+        // Quote without a response.
+        let body = "> <@registrar:matrix.org> Please sign the challenge";
+        assert_eq!(strip_reply_fallback(body), "");
+    }
+}
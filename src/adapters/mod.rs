@@ -1,9 +1,85 @@
+use crate::primitives::AccountType;
+use std::future::Future;
+use tokio::time::{self, Duration};
+
 mod display_name;
 pub(crate) mod email;
 mod matrix;
+mod rate_limiter;
+mod scheduler;
 pub(crate) mod twitter;
+mod webhook;
 
 pub use display_name::{DisplayNameHandler, VIOLATIONS_CAP};
 pub use email::{EmailHandler, EmailId, EmailTransport, SmtpImapClientBuilder};
 pub use matrix::{EventExtract, MatrixClient, MatrixHandler, MatrixTransport};
+pub use scheduler::ReminderScheduler;
 pub use twitter::{Twitter, TwitterBuilder, TwitterHandler, TwitterId, TwitterTransport};
+pub use webhook::WebhookNotifier;
+
+/// Default time after which a call to an external service (Matrix homeserver,
+/// Twitter API, SMTP/IMAP server, webhook endpoint) is aborted.
+pub const DEFAULT_ADAPTER_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Fail)]
+pub enum AdapterError {
+    #[fail(display = "{} adapter timed out on: {}", adapter, operation)]
+    Timeout {
+        adapter: AccountType,
+        operation: &'static str,
+    },
+}
+
+/// Awaits the given future, aborting with `AdapterError::Timeout` if it does
+/// not complete within `timeout`.
+async fn with_timeout<F: Future>(
+    adapter: AccountType,
+    operation: &'static str,
+    timeout: Duration,
+    fut: F,
+) -> Result<F::Output, AdapterError> {
+    time::timeout(timeout, fut)
+        .await
+        .map_err(|_| AdapterError::Timeout {
+            adapter: adapter,
+            operation: operation,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn with_timeout_aborts() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let res = with_timeout(
+                AccountType::Matrix,
+                "send message",
+                Duration::from_millis(50),
+                async { 1 },
+            )
+            .await
+            .unwrap();
+            assert_eq!(res, 1);
+
+            let err = with_timeout(
+                AccountType::Matrix,
+                "send message",
+                Duration::from_millis(50),
+                time::delay_for(Duration::from_secs(5)),
+            )
+            .await
+            .unwrap_err();
+
+            match err {
+                AdapterError::Timeout { adapter, operation } => {
+                    assert_eq!(adapter, AccountType::Matrix);
+                    assert_eq!(operation, "send message");
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,112 @@
+use std::result::Result as StdResult;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::Mutex;
+use tokio::time::{self, Duration};
+
+/// A leaky bucket which allows bursts of up to `capacity` messages and then
+/// refills at `refill_rate` messages per second.
+pub struct LeakyBucket {
+    capacity: u32,
+    refill_rate: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl LeakyBucket {
+    pub fn new(capacity: u32, refill_rate: f64) -> Self {
+        LeakyBucket {
+            capacity: capacity,
+            refill_rate: refill_rate,
+            tokens: capacity as f64,
+            last_refill: Instant::now(),
+        }
+    }
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * self.refill_rate).min(self.capacity as f64);
+        self.last_refill = now;
+    }
+    /// Takes a token from the bucket. If the bucket is empty, the duration
+    /// until the next token becomes available is returned.
+    pub fn try_acquire(&mut self) -> StdResult<(), Duration> {
+        self.refill();
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - self.tokens) / self.refill_rate,
+            ))
+        }
+    }
+}
+
+/// Shareable wrapper around `LeakyBucket`. Callers exceeding the rate limit
+/// are queued instead of having their messages dropped.
+#[derive(Clone)]
+pub struct RateLimiter {
+    bucket: Arc<Mutex<LeakyBucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(capacity: u32, refill_rate: f64) -> Self {
+        RateLimiter {
+            bucket: Arc::new(Mutex::new(LeakyBucket::new(capacity, refill_rate))),
+        }
+    }
+    pub async fn acquire(&self) {
+        loop {
+            let wait = match self.bucket.lock().await.try_acquire() {
+                Ok(()) => return,
+                Err(wait) => wait,
+            };
+
+            warn!(
+                "Rate limit reached, delaying message by {} ms",
+                wait.as_millis()
+            );
+
+            time::delay_for(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::sleep;
+
+    #[test]
+    fn leaky_bucket_burst() {
+        let mut bucket = LeakyBucket::new(3, 1.0);
+
+        assert!(bucket.try_acquire().is_ok());
+        assert!(bucket.try_acquire().is_ok());
+        assert!(bucket.try_acquire().is_ok());
+
+        let wait = bucket.try_acquire().unwrap_err();
+        assert!(wait > Duration::from_millis(0));
+        assert!(wait <= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn leaky_bucket_refill() {
+        let mut bucket = LeakyBucket::new(1, 50.0);
+
+        assert!(bucket.try_acquire().is_ok());
+        assert!(bucket.try_acquire().is_err());
+
+        // One token every 20ms.
+        sleep(Duration::from_millis(40));
+        assert!(bucket.try_acquire().is_ok());
+
+        // The bucket never exceeds its capacity.
+        sleep(Duration::from_millis(200));
+        assert!(bucket.try_acquire().is_ok());
+        assert!(bucket.try_acquire().is_err());
+    }
+}
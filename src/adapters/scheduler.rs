@@ -0,0 +1,202 @@
+use crate::comms::{CommsVerifier, Shutdown};
+use crate::primitives::{unix_time, AccountType, NetAccount, Result};
+use crate::Database;
+use std::collections::HashMap;
+use tokio::time::{self, Duration};
+
+/// Interval (in seconds) in which pending challenges are checked for due
+/// reminders.
+const REMINDER_CHECK_INTERVAL: u64 = 600;
+
+/// Account types which support sending the challenge again.
+const REMINDER_ACCOUNT_TYPES: [AccountType; 3] = [
+    AccountType::Matrix,
+    AccountType::Email,
+    AccountType::Twitter,
+];
+
+/// Reminds users about challenges they did not respond to. A reminder is
+/// sent every `delay` seconds after the judgement request was received, up
+/// to `max_reminders` times.
+pub struct ReminderScheduler {
+    db: Database,
+    delay: u64,
+    max_reminders: u32,
+    // Amount of reminders sent per account.
+    sent: HashMap<(NetAccount, AccountType), u32>,
+}
+
+impl ReminderScheduler {
+    pub fn new(db: Database, delay_hours: u64, max_reminders: u32) -> Self {
+        ReminderScheduler {
+            db: db,
+            delay: delay_hours * 3600,
+            max_reminders: max_reminders,
+            sent: HashMap::new(),
+        }
+    }
+    pub async fn start(mut self, comms: CommsVerifier, shutdown: Shutdown) {
+        let mut interval = time::interval(Duration::from_secs(REMINDER_CHECK_INTERVAL));
+
+        let mut shutdown = shutdown.subscribe();
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = shutdown.recv() => break,
+            }
+
+            let _ = self.local(&comms, unix_time()).await.map_err(|err| {
+                error!("{}", err);
+                err
+            });
+        }
+    }
+    async fn local(&mut self, comms: &CommsVerifier, now: u64) -> Result<()> {
+        let pending = self.db.select_pending_challenges().await?;
+
+        // Forget about accounts which are no longer pending.
+        self.sent.retain(|(net_account, account_ty), _| {
+            pending
+                .iter()
+                .any(|(n, a, _)| n == net_account && a == account_ty)
+        });
+
+        for (net_account, account_ty, created) in pending {
+            if !REMINDER_ACCOUNT_TYPES.contains(&account_ty) {
+                continue;
+            }
+
+            let due = due_reminders(created, now, self.delay, self.max_reminders);
+
+            // Accounts seen for the first time (e.g. after a restart) do not
+            // get the reminders which became due in the meantime.
+            let sent = self
+                .sent
+                .entry((net_account.clone(), account_ty.clone()))
+                .or_insert(due);
+
+            if due > *sent {
+                comms.notify_challenge_reminder(net_account, account_ty);
+                *sent = due;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn due_reminders(created: u64, now: u64, delay: u64, max_reminders: u32) -> u32 {
+    if delay == 0 {
+        return 0;
+    }
+
+    let due = now.saturating_sub(created) / delay;
+    due.min(max_reminders as u64) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comms::CommsMessage;
+    use crate::manager::OnChainIdentity;
+    use crate::primitives::{Account, Challenge, ChallengeStatus};
+    use tokio::runtime::Runtime;
+
+    const DELAY: u64 = 3600;
+
+    fn db_path() -> String {
+        format!("/tmp/sqlite_{}", Challenge::gen_random().as_str())
+    }
+
+    fn reminders(comms: &CommsVerifier) -> Vec<(NetAccount, AccountType)> {
+        let mut reminders = vec![];
+        while let Some(msg) = comms.try_recv() {
+            match msg {
+                CommsMessage::ChallengeReminder {
+                    net_account,
+                    account_ty,
+                } => reminders.push((net_account, account_ty)),
+                _ => panic!("unexpected message"),
+            }
+        }
+
+        reminders
+    }
+
+    #[test]
+    fn due_reminders_capped() {
+        assert_eq!(due_reminders(100, 100, DELAY, 3), 0);
+        assert_eq!(due_reminders(100, 100 + DELAY - 1, DELAY, 3), 0);
+        assert_eq!(due_reminders(100, 100 + DELAY, DELAY, 3), 1);
+        assert_eq!(due_reminders(100, 100 + DELAY * 2, DELAY, 3), 2);
+        assert_eq!(due_reminders(100, 100 + DELAY * 10, DELAY, 3), 3);
+        // Clock skew.
+        assert_eq!(due_reminders(100, 50, DELAY, 3), 0);
+        // Disabled.
+        assert_eq!(due_reminders(100, 100 + DELAY, DELAY, 0), 0);
+        assert_eq!(due_reminders(100, 100 + DELAY, 0, 3), 0);
+    }
+
+    #[test]
+    fn send_reminders() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let db = Database::new(&db_path()).unwrap();
+            let comms = CommsVerifier::new();
+            let mut scheduler = ReminderScheduler::new(db.clone(), 1, 2);
+
+            let alice = NetAccount::alice();
+            let mut ident = OnChainIdentity::new(alice.clone()).unwrap();
+            ident
+                .push_account(AccountType::Matrix, Account::from("@alice:matrix.org"))
+                .unwrap();
+            ident
+                .push_account(AccountType::Email, Account::from("alice@example.com"))
+                .unwrap();
+            ident
+                .push_account(AccountType::DisplayName, Account::from("Alice"))
+                .unwrap();
+            db.insert_identity(&ident).await.unwrap();
+
+            let now = unix_time();
+
+            // Nothing due yet.
+            scheduler.local(&comms, now).await.unwrap();
+            assert!(reminders(&comms).is_empty());
+
+            // First reminder, display names are skipped.
+            scheduler.local(&comms, now + DELAY).await.unwrap();
+            let res = reminders(&comms);
+            assert_eq!(res.len(), 2);
+            assert!(res.contains(&(alice.clone(), AccountType::Matrix)));
+            assert!(res.contains(&(alice.clone(), AccountType::Email)));
+
+            // Not sent twice.
+            scheduler.local(&comms, now + DELAY).await.unwrap();
+            assert!(reminders(&comms).is_empty());
+
+            // Accepted challenges do not get reminders.
+            db.set_challenge_status(&alice, &AccountType::Email, &ChallengeStatus::Accepted)
+                .await
+                .unwrap();
+
+            scheduler.local(&comms, now + DELAY * 2).await.unwrap();
+            assert_eq!(
+                reminders(&comms),
+                vec![(alice.clone(), AccountType::Matrix)]
+            );
+
+            // Maximum amount of reminders reached.
+            scheduler.local(&comms, now + DELAY * 10).await.unwrap();
+            assert!(reminders(&comms).is_empty());
+
+            // A restarted scheduler does not send missed reminders.
+            let mut scheduler = ReminderScheduler::new(db.clone(), 1, 5);
+            scheduler.local(&comms, now + DELAY * 3).await.unwrap();
+            assert!(reminders(&comms).is_empty());
+
+            scheduler.local(&comms, now + DELAY * 4).await.unwrap();
+            assert_eq!(reminders(&comms), vec![(alice, AccountType::Matrix)]);
+        });
+    }
+}
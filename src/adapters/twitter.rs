@@ -1,8 +1,9 @@
-use crate::comms::{CommsMessage, CommsVerifier};
+use crate::adapters::{with_timeout, AdapterError, DEFAULT_ADAPTER_TIMEOUT};
+use crate::comms::{CommsMessage, CommsVerifier, Shutdown};
 use crate::db::Database;
 use crate::manager::AccountStatus;
 use crate::primitives::{unix_time, Account, AccountType, Challenge, NetAccount, Result};
-use crate::verifier::{invalid_accounts_message, verification_handler, Verifier, VerifierMessage};
+use crate::verifier::{invalid_accounts_message, verification_response, Verifier, VerifierMessage};
 use reqwest::header::{self, HeaderValue};
 use reqwest::{Client, Request};
 use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, Value, ValueRef};
@@ -74,6 +75,8 @@ pub enum TwitterError {
     RequestBuilder(failure::Error),
     #[fail(display = "No Twitter account found for user: {}", 0)]
     NoTwitterAccount(String),
+    #[fail(display = "{}", 0)]
+    Timeout(AdapterError),
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -95,6 +98,7 @@ pub struct TwitterBuilder {
     token: Option<String>,
     token_secret: Option<String>,
     version: Option<f64>,
+    timeout: Option<Duration>,
 }
 
 impl TwitterBuilder {
@@ -107,6 +111,7 @@ impl TwitterBuilder {
             token: None,
             token_secret: None,
             version: None,
+            timeout: None,
         }
     }
     pub fn screen_name(mut self, account: Account) -> Self {
@@ -137,6 +142,12 @@ impl TwitterBuilder {
         self.version = Some(version);
         self
     }
+    /// Timeout of requests to the Twitter API. Defaults to
+    /// `DEFAULT_ADAPTER_TIMEOUT`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
     pub fn build(self) -> Result<Twitter> {
         Ok(Twitter {
             client: Client::new(),
@@ -149,6 +160,7 @@ impl TwitterBuilder {
             token: self.token.ok_or(TwitterError::IncompleteBuilder)?,
             token_secret: self.token_secret.ok_or(TwitterError::IncompleteBuilder)?,
             version: self.version.ok_or(TwitterError::IncompleteBuilder)?,
+            timeout: self.timeout.unwrap_or(DEFAULT_ADAPTER_TIMEOUT),
         })
     }
 }
@@ -163,6 +175,7 @@ pub struct Twitter {
     token: String,
     token_secret: String,
     version: f64,
+    timeout: Duration,
 }
 
 use hmac::{Hmac, Mac, NewMac};
@@ -217,7 +230,7 @@ impl TwitterHandler {
             comms: comms,
         }
     }
-    pub async fn start<T: Clone + TwitterTransport>(self, transport: T) {
+    pub async fn start<T: Clone + TwitterTransport>(self, transport: T, shutdown: Shutdown) {
         // TODO: Improve error case
         let my_id = transport
             .lookup_twitter_id(None, Some(&[transport.my_screen_name()]))
@@ -229,7 +242,8 @@ impl TwitterHandler {
         // Start incoming messages handler.
         let l_self = self.clone();
         let l_transport = transport.clone();
-        tokio::spawn(async move {
+        let mut l_shutdown = shutdown.subscribe();
+        let incoming = tokio::spawn(async move {
             loop {
                 let _ = l_self
                     .handle_incoming_messages(&l_transport, &my_id)
@@ -238,20 +252,31 @@ impl TwitterHandler {
                         error!("{}", err);
                     });
 
-                time::delay_for(Duration::from_secs(REQ_MESSAGE_TIMEOUT)).await;
+                tokio::select! {
+                    _ = time::delay_for(Duration::from_secs(REQ_MESSAGE_TIMEOUT)) => {}
+                    _ = l_shutdown.recv() => break,
+                }
             }
         });
 
+        let mut shutdown = shutdown.subscribe();
         loop {
-            let _ = self.local(&transport).await.map_err(|err| {
+            let msg = tokio::select! {
+                msg = self.comms.recv() => msg,
+                _ = shutdown.recv() => break,
+            };
+
+            let _ = self.local(&transport, msg).await.map_err(|err| {
                 error!("{}", err);
             });
         }
+
+        let _ = incoming.await;
     }
-    pub async fn local<T: TwitterTransport>(&self, transport: &T) -> Result<()> {
+    pub async fn local<T: TwitterTransport>(&self, transport: &T, msg: CommsMessage) -> Result<()> {
         use CommsMessage::*;
 
-        match self.comms.recv().await {
+        match msg {
             AccountToVerify {
                 net_account: _,
                 account: _,
@@ -447,12 +472,12 @@ impl TwitterHandler {
                 .for_each(|msg| verifier.verify(&msg.message));
 
             // Update challenge statuses and notify manager
-            verification_handler(&verifier, &self.db, &self.comms, &AccountType::Twitter).await?;
+            let response =
+                verification_response(&verifier, &self.db, &self.comms, &AccountType::Twitter)
+                    .await?;
 
             // Inform user about the current state of the verification
-            transport
-                .send_message(&twitter_id, verifier.response_message_builder())
-                .await?;
+            transport.send_message(&twitter_id, response).await?;
         }
 
         self.db
@@ -565,16 +590,19 @@ impl Twitter {
         self.authenticate_request(&HttpMethod::GET, url, &mut request, params)
             .map_err(|err| TwitterError::RequestBuilder(err.into()))?;
 
-        let resp = self
-            .client
-            .execute(request)
-            .await
-            .map_err(|err| TwitterError::Http(err.into()))?;
+        let txt = with_timeout(AccountType::Twitter, "GET request", self.timeout, async {
+            let resp = self
+                .client
+                .execute(request)
+                .await
+                .map_err(|err| TwitterError::Http(err.into()))?;
 
-        let txt = resp
-            .text()
-            .await
-            .map_err(|_| TwitterError::UnrecognizedData)?;
+            resp.text()
+                .await
+                .map_err(|_| TwitterError::UnrecognizedData)
+        })
+        .await
+        .map_err(TwitterError::Timeout)??;
 
         trace!("GET response: {}", txt);
 
@@ -601,16 +629,19 @@ impl Twitter {
         self.authenticate_request(&HttpMethod::POST, url, &mut request, None)
             .map_err(|err| TwitterError::RequestBuilder(err.into()))?;
 
-        let resp = self
-            .client
-            .execute(request)
-            .await
-            .map_err(|err| TwitterError::Http(err.into()))?;
+        let txt = with_timeout(AccountType::Twitter, "POST request", self.timeout, async {
+            let resp = self
+                .client
+                .execute(request)
+                .await
+                .map_err(|err| TwitterError::Http(err.into()))?;
 
-        let txt = resp
-            .text()
-            .await
-            .map_err(|_| TwitterError::UnrecognizedData)?;
+            resp.text()
+                .await
+                .map_err(|_| TwitterError::UnrecognizedData)
+        })
+        .await
+        .map_err(TwitterError::Timeout)??;
 
         trace!("POST response: {}", txt);
 
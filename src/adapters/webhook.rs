@@ -0,0 +1,281 @@
+use crate::comms::{CommsMessage, CommsVerifier, Shutdown};
+use crate::primitives::{unix_time, AccountType, Judgement, NetAccount, Result};
+use hmac::{Hmac, Mac, NewMac};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Client;
+use sha2::Sha256;
+use std::sync::Arc;
+use tokio::time::{self, Duration};
+
+/// The maximum number of attempts for delivering a single webhook message.
+const MAX_ATTEMPTS: u32 = 5;
+
+#[derive(Debug, Fail)]
+pub enum WebhookError {
+    #[fail(
+        display = "webhook endpoint rejected the message with status code: {}",
+        0
+    )]
+    Rejected(u16),
+    #[fail(display = "failed to deliver webhook message after {} attempts", 0)]
+    AttemptsExceeded(u32),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum WebhookEventType {
+    #[serde(rename = "identityFullyVerified")]
+    IdentityFullyVerified,
+    #[serde(rename = "judgementCompleted")]
+    JudgementCompleted,
+    #[serde(rename = "verificationFailed")]
+    VerificationFailed,
+    #[serde(rename = "verificationTimedOut")]
+    VerificationTimedOut,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct WebhookMessage {
+    pub event: WebhookEventType,
+    pub address: NetAccount,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub judgement: Option<Judgement>,
+    // The account types which failed verification.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub accounts: Vec<AccountType>,
+    pub timestamp: u64,
+}
+
+impl WebhookMessage {
+    fn new(event: WebhookEventType, address: NetAccount, judgement: Option<Judgement>) -> Self {
+        WebhookMessage {
+            event: event,
+            address: address,
+            judgement: judgement,
+            accounts: vec![],
+            timestamp: unix_time(),
+        }
+    }
+}
+
+/// Pushes judgement events to an operator specified URL. If a secret is
+/// configured, the JSON payload is signed with HMAC-SHA256 and the hex encoded
+/// signature is placed in the `X-Registrar-Signature` header.
+///
+/// Messages are delivered concurrently, so they might arrive out of order.
+/// Receivers can order them by their `timestamp`.
+pub struct WebhookNotifier {
+    client: Client,
+    url: String,
+    secret: Option<String>,
+}
+
+impl WebhookNotifier {
+    /// Creates the notifier. Each delivery attempt is aborted after
+    /// `timeout`.
+    pub fn new(url: String, secret: Option<String>, timeout: Duration) -> Result<Self> {
+        Ok(WebhookNotifier {
+            client: Client::builder().timeout(timeout).build()?,
+            url: url,
+            secret: secret,
+        })
+    }
+    pub async fn start(self, comms: CommsVerifier, shutdown: Shutdown) {
+        let notifier = Arc::new(self);
+
+        let mut shutdown = shutdown.subscribe();
+        loop {
+            let msg = tokio::select! {
+                msg = comms.recv() => msg,
+                _ = shutdown.recv() => break,
+            };
+
+            let message = match Self::local(msg) {
+                Some(message) => message,
+                None => continue,
+            };
+
+            // Deliver each message in its own task, so retries of an
+            // unreachable endpoint do not hold up the following events.
+            let notifier = Arc::clone(&notifier);
+            tokio::spawn(async move {
+                let _ = notifier.send_message(&message).await.map_err(|err| {
+                    error!("{}", err);
+                    err
+                });
+            });
+        }
+    }
+    fn local(msg: CommsMessage) -> Option<WebhookMessage> {
+        use CommsMessage::*;
+
+        match msg {
+            JudgeIdentity {
+                net_account,
+                judgement,
+            } => {
+                // Erroneous judgements are only issued for timed-out
+                // judgement requests.
+                let event = match judgement {
+                    Judgement::Reasonable => WebhookEventType::IdentityFullyVerified,
+                    Judgement::Erroneous => WebhookEventType::VerificationTimedOut,
+                };
+
+                Some(WebhookMessage::new(event, net_account, Some(judgement)))
+            }
+            JudgementGivenAck { net_account } => Some(WebhookMessage::new(
+                WebhookEventType::JudgementCompleted,
+                net_account,
+                None,
+            )),
+            VerificationFailed {
+                net_account,
+                accounts,
+            } => Some(WebhookMessage {
+                accounts: accounts,
+                ..WebhookMessage::new(WebhookEventType::VerificationFailed, net_account, None)
+            }),
+            _ => {
+                error!("Received unrecognized message type");
+                None
+            }
+        }
+    }
+    async fn send_message(&self, message: &WebhookMessage) -> Result<()> {
+        let body = serde_json::to_string(message)?;
+
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+
+            let mut request = self
+                .client
+                .post(&self.url)
+                .header(CONTENT_TYPE, "application/json")
+                .body(body.clone());
+
+            if let Some(secret) = self.secret.as_ref() {
+                request = request.header("X-Registrar-Signature", sign_payload(secret, &body));
+            }
+
+            // Only server-side and connection errors are retried, everything
+            // else is considered a permanent failure.
+            match request.send().await {
+                Ok(resp) if resp.status().is_success() => {
+                    debug!(
+                        "Delivered webhook message for address: {}",
+                        message.address.as_str()
+                    );
+                    return Ok(());
+                }
+                Ok(resp) if resp.status().is_server_error() => {
+                    warn!(
+                        "Webhook endpoint returned status code {}, attempt {}/{}",
+                        resp.status().as_u16(),
+                        attempt,
+                        MAX_ATTEMPTS
+                    );
+                }
+                Ok(resp) => return Err(WebhookError::Rejected(resp.status().as_u16()).into()),
+                Err(err) => {
+                    warn!(
+                        "Failed to reach webhook endpoint: {}, attempt {}/{}",
+                        err, attempt, MAX_ATTEMPTS
+                    );
+                }
+            }
+
+            if attempt == MAX_ATTEMPTS {
+                return Err(WebhookError::AttemptsExceeded(MAX_ATTEMPTS).into());
+            }
+
+            // Exponential back-off: 2s, 4s, 8s, 16s.
+            time::delay_for(Duration::from_secs(2u64.pow(attempt))).await;
+        }
+    }
+}
+
+fn sign_payload(secret: &str, payload: &str) -> String {
+    // HMAC accepts keys of any length, so this cannot fail.
+    let mut mac: Hmac<Sha256> = Hmac::new_varkey(secret.as_bytes()).unwrap();
+    mac.update(payload.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_payload_hmac_sha256() {
+        // Test case 2 of RFC 4231.
+        let sig = sign_payload("Jefe", "what do ya want for nothing?");
+        assert_eq!(
+            sig,
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn webhook_event_types() {
+        let msg = WebhookNotifier::local(CommsMessage::JudgeIdentity {
+            net_account: NetAccount::alice(),
+            judgement: Judgement::Reasonable,
+        })
+        .unwrap();
+        assert_eq!(msg.event, WebhookEventType::IdentityFullyVerified);
+
+        let msg = WebhookNotifier::local(CommsMessage::JudgeIdentity {
+            net_account: NetAccount::alice(),
+            judgement: Judgement::Erroneous,
+        })
+        .unwrap();
+        assert_eq!(msg.event, WebhookEventType::VerificationTimedOut);
+        assert_eq!(msg.judgement, Some(Judgement::Erroneous));
+
+        let msg = WebhookNotifier::local(CommsMessage::VerificationFailed {
+            net_account: NetAccount::alice(),
+            accounts: vec![AccountType::Email, AccountType::Twitter],
+        })
+        .unwrap();
+        assert_eq!(msg.event, WebhookEventType::VerificationFailed);
+        assert_eq!(msg.accounts, vec![AccountType::Email, AccountType::Twitter]);
+
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains("\"event\":\"verificationFailed\""));
+        assert!(json.contains("\"accounts\":[\"email\",\"twitter\"]"));
+    }
+
+    #[test]
+    fn webhook_message_format() {
+        let msg = WebhookMessage {
+            event: WebhookEventType::IdentityFullyVerified,
+            address: NetAccount::alice(),
+            judgement: Some(Judgement::Reasonable),
+            accounts: vec![],
+            timestamp: 0,
+        };
+
+        assert_eq!(
+            serde_json::to_string(&msg).unwrap(),
+            "{\"event\":\"identityFullyVerified\",\
+            \"address\":\"14GcE3qBiEnAyg2sDfadT3fQhWd2Z3M59tWi1CvVV8UwxUfU\",\
+            \"judgement\":\"reasonable\",\
+            \"timestamp\":0}"
+        );
+
+        let msg = WebhookMessage {
+            event: WebhookEventType::JudgementCompleted,
+            address: NetAccount::alice(),
+            judgement: None,
+            accounts: vec![],
+            timestamp: 0,
+        };
+
+        assert_eq!(
+            serde_json::to_string(&msg).unwrap(),
+            "{\"event\":\"judgementCompleted\",\
+            \"address\":\"14GcE3qBiEnAyg2sDfadT3fQhWd2Z3M59tWi1CvVV8UwxUfU\",\
+            \"timestamp\":0}"
+        );
+    }
+}
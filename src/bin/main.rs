@@ -2,23 +2,49 @@
 extern crate log;
 
 use failure::Error;
-use registrar::{block, init_env, run};
+use registrar::{block, init_env, run, RunOptions, Tasks};
 use registrar::{
-    Account, Database, HealthCheck, MatrixClient, SmtpImapClientBuilder, TwitterBuilder,
-    WebSocketReader, WebSocketWriter, WebSockets,
+    Account, AdminOptions, Connectivity, Database, HealthCheck, MatrixClient, ReminderScheduler,
+    SmtpImapClientBuilder, TwitterBuilder, WebSocketReader, WebSocketWriter, WebSockets,
+    WebhookNotifier, DEFAULT_ADAPTER_TIMEOUT,
 };
+use std::time::Duration;
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    let config = init_env()?;
+    let config = init_env()
+        .map_err(|err| {
+            error!("{}", err);
+            std::process::exit(1);
+        })
+        .unwrap();
 
     info!("Setting up database");
-    let db2 = Database::new(&config.registrar_db_path)?;
+    let db2 = Database::with_busy_timeout(
+        &config.registrar_db_path,
+        Duration::from_millis(config.sqlite_busy_timeout_ms),
+    )?;
+
+    let connectivity = Connectivity::default();
+    let mut tasks = Tasks::default();
 
     info!("Starting health check thread");
     if config.enable_health_check {
-        std::thread::spawn(|| {
-            HealthCheck::start()
+        let db = db2.clone();
+        let admin = AdminOptions {
+            enable_export: config.enable_admin_export,
+            enable_freeze: config.enable_admin_freeze,
+            token: config.admin_token.clone(),
+        };
+
+        let connectivity = if config.enable_accounts && config.enable_watcher {
+            Some(connectivity.clone())
+        } else {
+            None
+        };
+
+        std::thread::spawn(move || {
+            HealthCheck::start(db, connectivity, admin)
                 .map_err(|err| {
                     error!("Failed to start health check service: {}", err);
                     std::process::exit(1);
@@ -28,6 +54,11 @@ async fn main() -> Result<(), Error> {
     }
 
     if config.enable_accounts {
+        let adapter_timeout = config
+            .adapter_timeout_seconds
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_ADAPTER_TIMEOUT);
+
         info!("Setting up Matrix client");
         let matrix_transport = MatrixClient::new(
             &config.matrix_homeserver,
@@ -35,6 +66,8 @@ async fn main() -> Result<(), Error> {
             &config.matrix_password,
             &config.matrix_db_path,
             db2.clone(),
+            config.matrix_max_messages_per_second,
+            adapter_timeout,
         )
         .await?;
 
@@ -47,6 +80,7 @@ async fn main() -> Result<(), Error> {
             .token(config.twitter_token)
             .token_secret(config.twitter_token_secret)
             .version(1.0)
+            .timeout(adapter_timeout)
             .build()?;
 
         info!("Setting up Email client");
@@ -56,15 +90,45 @@ async fn main() -> Result<(), Error> {
             .email_inbox(config.email_inbox)
             .email_user(config.email_user)
             .email_password(config.email_password)
+            .timeout(adapter_timeout)
             .build()?;
 
+        let webhook = config
+            .webhook_url
+            .map(|url| {
+                info!("Setting up webhook notifier");
+                WebhookNotifier::new(url, config.webhook_secret, adapter_timeout)
+            })
+            .transpose()?;
+
+        let reminders = if config.max_reminders > 0 {
+            info!("Setting up challenge reminders");
+            Some(ReminderScheduler::new(
+                db2.clone(),
+                config.reminder_delay_hours,
+                config.max_reminders,
+            ))
+        } else {
+            None
+        };
+
+        let options = RunOptions {
+            enable_watcher: config.enable_watcher,
+            watcher_url: config.watcher_url,
+            connectivity: connectivity,
+            db_vacuum_interval_hours: config.db_vacuum_interval_hours,
+            comms_channel_capacity: config.comms_channel_capacity,
+        };
+
         run::<WebSockets, WebSocketWriter, WebSocketReader, _, _, _, _>(
-            config.enable_watcher,
-            config.watcher_url,
-            db2,
+            options,
+            &mut tasks,
+            db2.clone(),
             matrix_transport,
             twitter_transport,
             email_transport,
+            webhook,
+            reminders,
         )
         .await
         .map_err(|err| {
@@ -76,7 +140,8 @@ async fn main() -> Result<(), Error> {
         warn!("Accounts disabled. Nothing to do...");
     }
 
-    block().await;
+    block(tasks, db2).await?;
+    info!("Shutdown complete");
 
     Ok(())
 }
@@ -2,27 +2,189 @@ use crate::manager::{AccountStatus, OnChainIdentity};
 use crate::primitives::{Account, AccountType, Fatal, Judgement, NetAccount};
 #[cfg(test)]
 use crate::tests::mocks::MatrixEventMock;
-use crossbeam::channel::{unbounded, Receiver, Sender};
+use crossbeam::channel::{bounded, unbounded, Receiver, Sender, TrySendError};
+use futures::future;
 #[cfg(test)]
 use matrix_sdk::identifiers::{RoomId, UserId};
+use std::cmp::max;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::broadcast;
 use tokio::time::{self, Duration};
 
+/// Name of the manager task, used for logging.
+const MANAGER: &str = "Manager";
+
+/// Amount of messages dropped because the receiving channel was full.
+static DROPPED_MESSAGES: AtomicU64 = AtomicU64::new(0);
+
+/// Creates the channel from the manager to the task with the given name,
+/// which is only used for logging. The channel is unbounded if no capacity is
+/// specified.
 pub fn generate_comms(
     sender: Sender<CommsMessage>,
-    account_ty: AccountType,
+    target: String,
+    capacity: Option<usize>,
 ) -> (CommsMain, CommsVerifier) {
-    let (tx, recv) = unbounded();
+    let (tx, recv) = match capacity {
+        Some(capacity) => bounded(capacity),
+        None => unbounded(),
+    };
 
     (
-        CommsMain { sender: tx },
+        CommsMain {
+            sender: tx,
+            target: target,
+            almost_full: Arc::new(AtomicBool::new(false)),
+        },
         CommsVerifier {
             sender: sender,
             recv: recv,
-            address_ty: account_ty,
+            almost_full: Arc::new(AtomicBool::new(false)),
         },
     )
 }
 
+/// Warns once when the channel fills up past 80% of its capacity. The
+/// `almost_full` flag is reset once the channel drains below the threshold
+/// again, so a channel which stays full does not flood the logs.
+fn check_capacity(sender: &Sender<CommsMessage>, target: &str, almost_full: &AtomicBool) {
+    if let Some(capacity) = sender.capacity() {
+        let threshold = max(1, capacity * 8 / 10);
+        let len = sender.len();
+
+        if len >= threshold {
+            if !almost_full.swap(true, Ordering::Relaxed) {
+                warn!(
+                    "Communication channel to {} is almost full ({}/{})",
+                    target, len, capacity
+                );
+            }
+        } else {
+            almost_full.store(false, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Sends the message without blocking the producer. If the channel is full,
+/// the message is dropped and `false` is returned. Channels which carry
+/// judgement requests and judgements are unbounded and never drop messages,
+/// messages addressed to users are sent with `send_wait`. `target` is the
+/// name of the receiving task.
+fn send(
+    sender: &Sender<CommsMessage>,
+    target: &str,
+    almost_full: &AtomicBool,
+    msg: CommsMessage,
+) -> bool {
+    check_capacity(sender, target, almost_full);
+
+    match sender.try_send(msg) {
+        Err(TrySendError::Full(msg)) => {
+            let dropped = DROPPED_MESSAGES.fetch_add(1, Ordering::Relaxed) + 1;
+            error!(
+                "Communication channel to {} is full, dropping {} message (total dropped: {})",
+                target,
+                msg.name(),
+                dropped
+            );
+
+            false
+        }
+        res => {
+            res.fatal();
+            true
+        }
+    }
+}
+
+/// Sends the message, waiting for space to become available if the channel
+/// is full. Used for messages which must reach the user, such as challenges.
+async fn send_wait(
+    sender: &Sender<CommsMessage>,
+    target: &str,
+    almost_full: &AtomicBool,
+    mut msg: CommsMessage,
+) {
+    check_capacity(sender, target, almost_full);
+
+    let mut logged = false;
+    loop {
+        match sender.try_send(msg) {
+            Err(TrySendError::Full(m)) => {
+                if !logged {
+                    warn!(
+                        "Communication channel to {} is full, waiting to send {} message",
+                        target,
+                        m.name()
+                    );
+                    logged = true;
+                }
+
+                msg = m;
+                time::delay_for(Duration::from_millis(10)).await;
+            }
+            res => {
+                res.fatal();
+                return;
+            }
+        }
+    }
+}
+
+/// Tells the long running tasks to stop. Tasks only check for the signal in
+/// between messages, so the message currently being handled is finished.
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    sender: broadcast::Sender<()>,
+    triggered: Arc<AtomicBool>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(1);
+
+        Shutdown {
+            sender: sender,
+            triggered: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+impl Shutdown {
+    pub fn subscribe(&self) -> ShutdownListener {
+        ShutdownListener {
+            recv: self.sender.subscribe(),
+            triggered: Arc::clone(&self.triggered),
+        }
+    }
+    pub fn trigger(&self) {
+        self.triggered.store(true, Ordering::SeqCst);
+        // Only fails if no task is listening.
+        let _ = self.sender.send(());
+    }
+}
+
+pub struct ShutdownListener {
+    recv: broadcast::Receiver<()>,
+    // Covers listeners which subscribed after the signal was sent.
+    triggered: Arc<AtomicBool>,
+}
+
+impl ShutdownListener {
+    /// Completes once the shutdown was triggered. Never completes if all
+    /// `Shutdown` handles were dropped without triggering it.
+    pub async fn recv(&mut self) {
+        if self.triggered.load(Ordering::SeqCst) {
+            return;
+        }
+
+        if let Err(broadcast::RecvError::Closed) = self.recv.recv().await {
+            future::pending::<()>().await;
+        }
+    }
+}
+
 pub enum CommsMessage {
     NewJudgementRequest(OnChainIdentity),
     JudgeIdentity {
@@ -51,6 +213,14 @@ pub enum CommsMessage {
     JudgementGivenAck {
         net_account: NetAccount,
     },
+    ChallengeReminder {
+        net_account: NetAccount,
+        account_ty: AccountType,
+    },
+    VerificationFailed {
+        net_account: NetAccount,
+        accounts: Vec<AccountType>,
+    },
     // Only used to manually trigger the event handler in tests, since the
     // matrix sdk runs the EventEmitter in the background.
     #[cfg(test)]
@@ -61,48 +231,108 @@ pub enum CommsMessage {
     },
 }
 
+impl CommsMessage {
+    fn name(&self) -> &'static str {
+        use CommsMessage::*;
+
+        match self {
+            NewJudgementRequest(_) => "NewJudgementRequest",
+            JudgeIdentity { .. } => "JudgeIdentity",
+            LeaveRoom { .. } => "LeaveRoom",
+            AccountToVerify { .. } => "AccountToVerify",
+            NotifyStatusChange { .. } => "NotifyStatusChange",
+            MessageAcknowledged => "MessageAcknowledged",
+            NotifyInvalidAccount { .. } => "NotifyInvalidAccount",
+            ExistingDisplayNames { .. } => "ExistingDisplayNames",
+            JudgementGivenAck { .. } => "JudgementGivenAck",
+            ChallengeReminder { .. } => "ChallengeReminder",
+            VerificationFailed { .. } => "VerificationFailed",
+            #[cfg(test)]
+            TriggerMatrixEmitter { .. } => "TriggerMatrixEmitter",
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct CommsMain {
     sender: Sender<CommsMessage>,
+    // The name of the receiving task.
+    target: String,
+    almost_full: Arc<AtomicBool>,
 }
 
 impl CommsMain {
-    pub fn notify_account_verification(&self, net_account: NetAccount, account: Account) {
-        self.sender
-            .send(CommsMessage::AccountToVerify {
+    pub async fn notify_account_verification(&self, net_account: NetAccount, account: Account) {
+        send_wait(
+            &self.sender,
+            &self.target,
+            &self.almost_full,
+            CommsMessage::AccountToVerify {
                 net_account: net_account,
                 account: account,
-            })
-            .fatal();
+            },
+        )
+        .await;
     }
     pub fn notify_identity_judgment(&self, net_account: NetAccount, judgment: Judgement) {
-        self.sender
-            .send(CommsMessage::JudgeIdentity {
+        send(
+            &self.sender,
+            &self.target,
+            &self.almost_full,
+            CommsMessage::JudgeIdentity {
                 net_account: net_account,
                 judgement: judgment,
-            })
-            .fatal();
+            },
+        );
+    }
+    pub fn notify_judgement_completed(&self, net_account: NetAccount) {
+        send(
+            &self.sender,
+            &self.target,
+            &self.almost_full,
+            CommsMessage::JudgementGivenAck {
+                net_account: net_account,
+            },
+        );
     }
     pub fn leave_matrix_room(&self, net_account: NetAccount) {
-        self.sender
-            .send(CommsMessage::LeaveRoom {
+        send(
+            &self.sender,
+            &self.target,
+            &self.almost_full,
+            CommsMessage::LeaveRoom {
                 net_account: net_account,
-            })
-            .fatal();
+            },
+        );
     }
-    pub fn notify_invalid_accounts(
+    pub async fn notify_invalid_accounts(
         &self,
         net_account: NetAccount,
         account: Account,
         accounts: Vec<(AccountType, Account, AccountStatus)>,
     ) {
-        self.sender
-            .send(CommsMessage::NotifyInvalidAccount {
+        send_wait(
+            &self.sender,
+            &self.target,
+            &self.almost_full,
+            CommsMessage::NotifyInvalidAccount {
                 net_account: net_account,
                 account: account,
                 accounts: accounts,
-            })
-            .fatal()
+            },
+        )
+        .await;
+    }
+    pub fn notify_verification_failed(&self, net_account: NetAccount, accounts: Vec<AccountType>) {
+        send(
+            &self.sender,
+            &self.target,
+            &self.almost_full,
+            CommsMessage::VerificationFailed {
+                net_account: net_account,
+                accounts: accounts,
+            },
+        );
     }
     #[cfg(test)]
     pub fn trigger_matrix_emitter(
@@ -111,13 +341,16 @@ impl CommsMain {
         my_user_id: UserId,
         event: MatrixEventMock,
     ) {
-        self.sender
-            .send(CommsMessage::TriggerMatrixEmitter {
+        send(
+            &self.sender,
+            &self.target,
+            &self.almost_full,
+            CommsMessage::TriggerMatrixEmitter {
                 room_id: room_id,
                 my_user_id: my_user_id,
                 event: event,
-            })
-            .fatal()
+            },
+        );
     }
 }
 
@@ -125,7 +358,7 @@ impl CommsMain {
 pub struct CommsVerifier {
     sender: Sender<CommsMessage>,
     recv: Receiver<CommsMessage>,
-    address_ty: AccountType,
+    almost_full: Arc<AtomicBool>,
 }
 
 impl CommsVerifier {
@@ -138,7 +371,7 @@ impl CommsVerifier {
         CommsVerifier {
             sender: tx,
             recv: recv,
-            address_ty: AccountType::Matrix,
+            almost_full: Arc::new(AtomicBool::new(false)),
         }
     }
     pub async fn recv(&self) -> CommsMessage {
@@ -155,30 +388,178 @@ impl CommsVerifier {
         self.recv.try_recv().ok()
     }
     pub fn notify_new_identity(&self, ident: OnChainIdentity) {
-        self.sender
-            .send(CommsMessage::NewJudgementRequest(ident))
-            .fatal();
+        send(
+            &self.sender,
+            MANAGER,
+            &self.almost_full,
+            CommsMessage::NewJudgementRequest(ident),
+        );
     }
     pub fn notify_status_change(&self, net_account: NetAccount) {
-        self.sender
-            .send(CommsMessage::NotifyStatusChange {
+        send(
+            &self.sender,
+            MANAGER,
+            &self.almost_full,
+            CommsMessage::NotifyStatusChange {
                 net_account: net_account,
-            })
-            .fatal()
+            },
+        );
     }
     pub fn notify_ack(&self) {
-        self.sender.send(CommsMessage::MessageAcknowledged).fatal();
+        send(
+            &self.sender,
+            MANAGER,
+            &self.almost_full,
+            CommsMessage::MessageAcknowledged,
+        );
     }
     pub fn notify_judgement_given_ack(&self, net_account: NetAccount) {
-        self.sender
-            .send(CommsMessage::JudgementGivenAck {
+        send(
+            &self.sender,
+            MANAGER,
+            &self.almost_full,
+            CommsMessage::JudgementGivenAck {
                 net_account: net_account,
-            })
-            .fatal()
+            },
+        );
     }
     pub fn notify_existing_display_names(&self, accounts: Vec<(Account, NetAccount)>) {
-        self.sender
-            .send(CommsMessage::ExistingDisplayNames { accounts: accounts })
-            .fatal()
+        send(
+            &self.sender,
+            MANAGER,
+            &self.almost_full,
+            CommsMessage::ExistingDisplayNames { accounts: accounts },
+        );
+    }
+    pub fn notify_challenge_reminder(&self, net_account: NetAccount, account_ty: AccountType) {
+        send(
+            &self.sender,
+            MANAGER,
+            &self.almost_full,
+            CommsMessage::ChallengeReminder {
+                net_account: net_account,
+                account_ty: account_ty,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_channel_drops_messages() {
+        let (tx, recv) = bounded(2);
+        let almost_full = AtomicBool::new(false);
+
+        assert!(send(
+            &tx,
+            MANAGER,
+            &almost_full,
+            CommsMessage::MessageAcknowledged
+        ));
+        assert!(send(
+            &tx,
+            MANAGER,
+            &almost_full,
+            CommsMessage::MessageAcknowledged
+        ));
+        assert!(!send(
+            &tx,
+            MANAGER,
+            &almost_full,
+            CommsMessage::MessageAcknowledged
+        ));
+        assert_eq!(recv.len(), 2);
+
+        // Space becomes available again.
+        recv.try_recv().unwrap();
+        assert!(send(
+            &tx,
+            MANAGER,
+            &almost_full,
+            CommsMessage::MessageAcknowledged
+        ));
+        assert_eq!(recv.len(), 2);
+    }
+
+    #[test]
+    fn almost_full_warning_is_latched() {
+        // 80% of a capacity of 2 rounds down to zero, the threshold must
+        // still be at least one message.
+        let (tx, recv) = bounded(2);
+        let almost_full = AtomicBool::new(false);
+
+        check_capacity(&tx, MANAGER, &almost_full);
+        assert!(!almost_full.load(Ordering::Relaxed));
+
+        tx.try_send(CommsMessage::MessageAcknowledged).unwrap();
+        check_capacity(&tx, MANAGER, &almost_full);
+        assert!(almost_full.load(Ordering::Relaxed));
+
+        // Stays set while above the threshold, even if it was jumped past.
+        tx.try_send(CommsMessage::MessageAcknowledged).unwrap();
+        check_capacity(&tx, MANAGER, &almost_full);
+        assert!(almost_full.load(Ordering::Relaxed));
+
+        // Reset once the channel drained.
+        recv.try_recv().unwrap();
+        recv.try_recv().unwrap();
+        check_capacity(&tx, MANAGER, &almost_full);
+        assert!(!almost_full.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn full_channel_waits_for_user_messages() {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let (main_tx, _) = unbounded();
+            let (cm, cv) = generate_comms(main_tx, "Matrix".to_string(), Some(1));
+
+            cm.notify_account_verification(NetAccount::alice(), Account::from("@alice:matrix.org"))
+                .await;
+
+            // The channel is full, the second message is delivered once the
+            // first one was received.
+            let handle = tokio::spawn(async move {
+                cm.notify_account_verification(NetAccount::bob(), Account::from("@bob:matrix.org"))
+                    .await;
+            });
+
+            time::delay_for(Duration::from_millis(50)).await;
+            assert_eq!(cv.recv.len(), 1);
+
+            match cv.recv().await {
+                CommsMessage::AccountToVerify { net_account, .. } => {
+                    assert_eq!(net_account, NetAccount::alice())
+                }
+                _ => panic!("unexpected message"),
+            }
+
+            handle.await.unwrap();
+            match cv.recv().await {
+                CommsMessage::AccountToVerify { net_account, .. } => {
+                    assert_eq!(net_account, NetAccount::bob())
+                }
+                _ => panic!("unexpected message"),
+            }
+        });
+    }
+
+    #[test]
+    fn unbounded_channel_keeps_messages() {
+        let (main_tx, _) = unbounded();
+        let (cm, cv) = generate_comms(main_tx, "Connector".to_string(), None);
+
+        for _ in 0..10_000 {
+            cm.notify_identity_judgment(NetAccount::alice(), Judgement::Reasonable);
+        }
+
+        let mut count = 0;
+        while cv.try_recv().is_some() {
+            count += 1;
+        }
+        assert_eq!(count, 10_000);
     }
 }
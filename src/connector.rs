@@ -1,20 +1,140 @@
-use crate::comms::{CommsMessage, CommsVerifier};
+use crate::comms::{CommsMessage, CommsVerifier, Shutdown};
 use crate::manager::OnChainIdentity;
-use crate::primitives::{unix_time, Account, AccountType, Judgement, NetAccount, Result};
+use crate::primitives::{unix_time, Account, AccountType, Fatal, Judgement, NetAccount, Result};
 use futures::sink::SinkExt;
 use futures::stream::{SplitSink, SplitStream};
 use futures::{StreamExt, TryStreamExt};
 use futures_channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
 use serde_json::Value;
+use std::cmp::{max, min};
 use std::collections::HashMap;
 use std::convert::TryFrom;
+use std::fmt;
+use std::mem;
+use std::result::Result as StdResult;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::net::TcpStream;
 use tokio::sync::RwLock;
 use tokio::time::{self, Duration};
 use tokio_tungstenite::{connect_async, WebSocketStream};
 use tungstenite::protocol::Message as TungMessage;
 
+/// Delay before the second attempt to reconnect to the Watcher. The first
+/// attempt is made immediately.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// Upper bound of the delay between reconnection attempts.
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(300);
+
+/// Doubles the delay between reconnection attempts, starting at
+/// `RECONNECT_DELAY` and capped at `MAX_RECONNECT_DELAY`.
+fn next_reconnect_delay(delay: Duration) -> Duration {
+    min(max(delay * 2, RECONNECT_DELAY), MAX_RECONNECT_DELAY)
+}
+
+/// The lifecycle of the connection to the Watcher.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ConnectivityState {
+    Connecting { attempt: u32 },
+    Connected { since: Instant },
+    Reconnecting { attempt: u32, last_error: String },
+    Failed,
+}
+
+/// Events which advance the `ConnectivityState`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ConnectivityEvent {
+    Connected,
+    // A connection attempt failed or an established connection was closed.
+    ConnectionFailed(String),
+    // No further connection attempts are made.
+    GaveUp,
+}
+
+impl ConnectivityState {
+    fn next(&self, event: ConnectivityEvent) -> ConnectivityState {
+        use ConnectivityState::*;
+
+        match (self, event) {
+            // Final state.
+            (Failed, _) => Failed,
+            (_, ConnectivityEvent::GaveUp) => Failed,
+            (_, ConnectivityEvent::Connected) => Connected {
+                since: Instant::now(),
+            },
+            (Connecting { attempt }, ConnectivityEvent::ConnectionFailed(_)) => Connecting {
+                attempt: attempt + 1,
+            },
+            (Connected { .. }, ConnectivityEvent::ConnectionFailed(err)) => Reconnecting {
+                attempt: 1,
+                last_error: err,
+            },
+            (Reconnecting { attempt, .. }, ConnectivityEvent::ConnectionFailed(err)) => {
+                Reconnecting {
+                    attempt: attempt + 1,
+                    last_error: err,
+                }
+            }
+        }
+    }
+}
+
+impl Default for ConnectivityState {
+    fn default() -> Self {
+        ConnectivityState::Connecting { attempt: 1 }
+    }
+}
+
+impl fmt::Display for ConnectivityState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ConnectivityState::*;
+
+        match self {
+            Connecting { attempt } => write!(f, "connecting (attempt {})", attempt),
+            Connected { .. } => write!(f, "connected"),
+            Reconnecting {
+                attempt,
+                last_error,
+            } => write!(
+                f,
+                "reconnecting (attempt {}, last error: {})",
+                attempt, last_error
+            ),
+            Failed => write!(f, "failed"),
+        }
+    }
+}
+
+/// Shared handle to the connection state of the Watcher connector, read by
+/// the health check.
+#[derive(Debug, Clone, Default)]
+pub struct Connectivity {
+    state: Arc<std::sync::RwLock<ConnectivityState>>,
+}
+
+impl Connectivity {
+    pub fn state(&self) -> ConnectivityState {
+        self.state.read().fatal().clone()
+    }
+    pub fn transition(&self, event: ConnectivityEvent) {
+        let mut state = self.state.write().fatal();
+        let next = state.next(event);
+
+        if *state != next {
+            // Only changes of the state itself are logged at info level, not
+            // every increment of the attempt counter.
+            if mem::discriminant(&*state) != mem::discriminant(&next) {
+                info!("Watcher connection state: {} -> {}", *state, next);
+            } else {
+                debug!("Watcher connection state: {} -> {}", *state, next);
+            }
+
+            *state = next;
+        }
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub enum EventType {
     #[serde(rename = "ack")]
@@ -101,10 +221,81 @@ impl TryFrom<JudgementRequest> for OnChainIdentity {
     }
 }
 
+/// Amount of messages received from the Watcher which failed validation.
+static INVALID_MESSAGES: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Fail)]
+pub enum ValidationError {
+    #[fail(display = "malformed message: {}", 0)]
+    Malformed(serde_json::Error),
+    #[fail(display = "judgement request without an address")]
+    EmptyAddress,
+    #[fail(display = "judgement request for {} without any accounts", 0)]
+    NoAccounts(String),
+}
+
+fn validate_judgement_request(request: &JudgementRequest) -> StdResult<(), ValidationError> {
+    if request.address.as_str().is_empty() {
+        return Err(ValidationError::EmptyAddress);
+    }
+
+    if request.accounts.values().all(|account| account.is_none()) {
+        return Err(ValidationError::NoAccounts(
+            request.address.as_str().to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// A message from the Watcher as returned by `validate_watcher_message`.
+/// Judgement requests are already parsed, so the handler does not need to
+/// deserialize them again.
+#[derive(Debug)]
+pub(crate) enum WatcherMessage {
+    NewJudgementRequest(JudgementRequest),
+    PendingJudgementsResponse(Vec<JudgementRequest>),
+    Other(Message),
+}
+
+/// Parses a raw message from the Watcher and checks the contained judgement
+/// requests, if any. The entries of a `pendingJudgementsResponse` are only
+/// checked for their format, so that a single invalid entry does not discard
+/// the others.
+pub(crate) fn validate_watcher_message(raw: &str) -> StdResult<WatcherMessage, ValidationError> {
+    let msg = serde_json::from_str::<Message>(raw).map_err(ValidationError::Malformed)?;
+
+    match msg.event {
+        EventType::NewJudgementRequest => {
+            let request = serde_json::from_value::<JudgementRequest>(msg.data)
+                .map_err(ValidationError::Malformed)?;
+
+            validate_judgement_request(&request)?;
+
+            Ok(WatcherMessage::NewJudgementRequest(request))
+        }
+        EventType::PendingJudgementsResponse => {
+            let requests = serde_json::from_value::<Vec<JudgementRequest>>(msg.data)
+                .map_err(ValidationError::Malformed)?;
+
+            Ok(WatcherMessage::PendingJudgementsResponse(requests))
+        }
+        _ => Ok(WatcherMessage::Other(msg)),
+    }
+}
+
+fn count_invalid_message(err: &ValidationError) {
+    let invalid = INVALID_MESSAGES.fetch_add(1, Ordering::Relaxed) + 1;
+    warn!(
+        "Received invalid message from Watcher: {} (total invalid: {})",
+        err, invalid
+    );
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub(crate) struct JudgementGiven {
-    address: NetAccount,
-    result: String,
+    pub address: NetAccount,
+    pub result: String,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
@@ -193,6 +384,7 @@ pub struct Connector<W, R, P> {
     reader: R,
     comms: CommsVerifier,
     endpoint: P,
+    connectivity: Connectivity,
 }
 
 impl<
@@ -204,14 +396,21 @@ impl<
     pub async fn new<T: ConnectorInitTransports<W, R, Endpoint = P>>(
         endpoint: P,
         comms: CommsVerifier,
+        connectivity: Connectivity,
     ) -> Result<Self> {
-        let (writer, reader) = T::init(endpoint.clone()).await?;
+        let (writer, reader) = T::init(endpoint.clone()).await.map_err(|err| {
+            connectivity.transition(ConnectivityEvent::ConnectionFailed(err.to_string()));
+            err
+        })?;
+
+        connectivity.transition(ConnectivityEvent::Connected);
 
         Ok(Connector {
             writer: writer,
             reader: reader,
             comms: comms,
             endpoint: endpoint,
+            connectivity: connectivity,
         })
     }
     #[cfg(test)]
@@ -219,7 +418,11 @@ impl<
         self.writer = writer;
         self.reader = reader;
     }
-    pub async fn start<T: ConnectorInitTransports<W, R, Endpoint = P>>(mut self) {
+    pub async fn start<T: ConnectorInitTransports<W, R, Endpoint = P>>(
+        mut self,
+        shutdown: Shutdown,
+    ) {
+        let mut shutdown = shutdown.subscribe();
         loop {
             let (mut sender, receiver) = unbounded();
             let exit_token = Arc::new(RwLock::new(false));
@@ -273,21 +476,48 @@ impl<
             // Wait for the reader to exit, which in return will close the comms
             // receiver and writer task. This occurs when the connection to the
             // Watcher is closed.
-            handle.await.unwrap();
+            tokio::select! {
+                res = handle => res.unwrap(),
+                _ = shutdown.recv() => {
+                    debug!("Closing Watcher connection");
+                    *exit_token.write().await = true;
+                    return;
+                }
+            }
 
-            let mut interval = time::interval(Duration::from_secs(5));
+            self.connectivity
+                .transition(ConnectivityEvent::ConnectionFailed(
+                    "connection closed".to_string(),
+                ));
+
+            let mut delay = Duration::from_secs(0);
 
             info!("Trying to reconnect to Watcher...");
             loop {
-                interval.tick().await;
-                if let Ok((writer, reader)) = T::init(self.endpoint.clone()).await {
-                    info!("Connected successfully to Watcher, spawning tasks");
-                    self.writer = writer;
-                    self.reader = reader;
-
-                    break;
-                } else {
-                    trace!("Connection to Watcher failed...");
+                tokio::select! {
+                    _ = time::delay_for(delay) => {}
+                    _ = shutdown.recv() => return,
+                }
+
+                match T::init(self.endpoint.clone()).await {
+                    Ok((writer, reader)) => {
+                        info!("Connected successfully to Watcher, spawning tasks");
+                        self.connectivity.transition(ConnectivityEvent::Connected);
+                        self.writer = writer;
+                        self.reader = reader;
+
+                        break;
+                    }
+                    Err(err) => {
+                        delay = next_reconnect_delay(delay);
+                        warn!(
+                            "Failed to reconnect to Watcher: {}, retrying in {}s",
+                            err,
+                            delay.as_secs()
+                        );
+                        self.connectivity
+                            .transition(ConnectivityEvent::ConnectionFailed(err.to_string()));
+                    }
                 }
             }
         }
@@ -379,106 +609,104 @@ impl<
                 if let Some(message) = message {
                     trace!("Received message: {:?}", message);
 
-                    let try_msg = serde_json::from_str::<Message>(&message);
-                    let msg = if let Ok(msg) = try_msg {
-                        msg
-                    } else {
-                        sender.send(Message::error()).await.unwrap();
+                    let msg = match validate_watcher_message(&message) {
+                        Ok(msg) => msg,
+                        Err(err) => {
+                            count_invalid_message(&err);
+                            sender.send(Message::error()).await.unwrap();
 
-                        return;
+                            continue;
+                        }
                     };
 
-                    match msg.event {
-                        NewJudgementRequest => {
+                    match msg {
+                        WatcherMessage::NewJudgementRequest(request) => {
                             info!("Received a new judgement request");
-                            if let Ok(request) =
-                                serde_json::from_value::<JudgementRequest>(msg.data)
-                            {
+                            if let Ok(ident) = OnChainIdentity::try_from(request) {
+                                comms.notify_new_identity(ident);
+                                sender.send(Message::ack(None)).await.unwrap();
+                            } else {
+                                error!("Invalid `newJudgementRequest` message format");
+                                sender.send(Message::error()).await.unwrap();
+                            };
+                        }
+                        WatcherMessage::PendingJudgementsResponse(requests) => {
+                            trace!("Received pending challenges");
+                            if requests.is_empty() {
+                                trace!("The pending judgement list is empty. Waiting...");
+                            } else {
+                                info!("Pending judgement requests: {:?}", requests);
+                            }
+
+                            for request in requests {
+                                if let Err(err) = validate_judgement_request(&request) {
+                                    count_invalid_message(&err);
+                                    sender.send(Message::error()).await.unwrap();
+
+                                    continue;
+                                }
+
                                 if let Ok(ident) = OnChainIdentity::try_from(request) {
+                                    sender.send(Message::ack(None)).await.unwrap();
                                     comms.notify_new_identity(ident);
                                     sender.send(Message::ack(None)).await.unwrap();
                                 } else {
                                     error!("Invalid `newJudgementRequest` message format");
                                     sender.send(Message::error()).await.unwrap();
                                 };
-                            } else {
-                                error!("Invalid `newJudgementRequest` message format");
-                                sender.send(Message::error()).await.unwrap();
                             }
                         }
-                        Ack => {
-                            if let Ok(msg) = serde_json::from_value::<AckResponse>(msg.data.clone())
-                            {
-                                trace!("Received acknowledgement: {}", msg.result);
-                                comms.notify_ack();
-                            } else if let Ok(msg) =
-                                serde_json::from_value::<JudgementGiven>(msg.data)
-                            {
-                                if msg.result.to_lowercase() == "judgement given" {
-                                    info!(
-                                        "Received judgement acknowledgement for address: {}",
-                                        msg.address.as_str()
-                                    );
-                                    comms.notify_judgement_given_ack(msg.address)
+                        WatcherMessage::Other(msg) => match msg.event {
+                            Ack => {
+                                if let Ok(msg) =
+                                    serde_json::from_value::<AckResponse>(msg.data.clone())
+                                {
+                                    trace!("Received acknowledgement: {}", msg.result);
+                                    comms.notify_ack();
+                                } else if let Ok(msg) =
+                                    serde_json::from_value::<JudgementGiven>(msg.data)
+                                {
+                                    if msg.result.to_lowercase() == "judgement given" {
+                                        info!(
+                                            "Received judgement acknowledgement for address: {}",
+                                            msg.address.as_str()
+                                        );
+                                        comms.notify_judgement_given_ack(msg.address)
+                                    } else {
+                                        error!("Invalid 'acknowledgement' message format");
+                                    }
                                 } else {
                                     error!("Invalid 'acknowledgement' message format");
                                 }
-                            } else {
-                                error!("Invalid 'acknowledgement' message format");
                             }
-                        }
-                        Error => {
-                            if let Ok(msg) = serde_json::from_value::<ErrorResponse>(msg.data) {
-                                error!("Received error message: {}", msg.error);
-                            } else {
-                                error!("Invalid 'error' message format");
-                            }
-                        }
-                        PendingJudgementsResponse => {
-                            trace!("Received pending challenges");
-                            if let Ok(requests) =
-                                serde_json::from_value::<Vec<JudgementRequest>>(msg.data)
-                            {
-                                if requests.is_empty() {
-                                    trace!("The pending judgement list is empty. Waiting...");
+                            Error => {
+                                if let Ok(msg) = serde_json::from_value::<ErrorResponse>(msg.data) {
+                                    error!("Received error message: {}", msg.error);
                                 } else {
-                                    info!("Pending judgement requests: {:?}", requests);
+                                    error!("Invalid 'error' message format");
                                 }
-
-                                for request in requests {
-                                    if let Ok(ident) = OnChainIdentity::try_from(request) {
-                                        sender.send(Message::ack(None)).await.unwrap();
-                                        comms.notify_new_identity(ident);
-                                        sender.send(Message::ack(None)).await.unwrap();
-                                    } else {
-                                        error!("Invalid `newJudgementRequest` message format");
-                                        sender.send(Message::error()).await.unwrap();
-                                    };
+                            }
+                            DisplayNamesResponse => {
+                                trace!("Received display names response");
+                                trace!("Display names {:?}", msg.data);
+
+                                if let Ok(display_names) =
+                                    serde_json::from_value::<Vec<DisplayNamesEntry>>(msg.data)
+                                {
+                                    comms.notify_existing_display_names(
+                                        display_names
+                                            .into_iter()
+                                            .map(|e| (e.display_name, e.address))
+                                            .collect(),
+                                    );
+                                } else {
+                                    error!("Invalid `displayNamesResponse` message format");
                                 }
-                            } else {
-                                error!("Invalid `pendingChallengesRequest` message format");
                             }
-                        }
-                        DisplayNamesResponse => {
-                            trace!("Received display names response");
-                            trace!("Display names {:?}", msg.data);
-
-                            if let Ok(display_names) =
-                                serde_json::from_value::<Vec<DisplayNamesEntry>>(msg.data)
-                            {
-                                comms.notify_existing_display_names(
-                                    display_names
-                                        .into_iter()
-                                        .map(|e| (e.display_name, e.address))
-                                        .collect(),
-                                );
-                            } else {
-                                error!("Invalid `displayNamesResponse` message format");
+                            _ => {
+                                warn!("Received unrecognized message: '{:?}'", msg);
                             }
-                        }
-                        _ => {
-                            warn!("Received unrecognized message: '{:?}'", msg);
-                        }
+                        },
                     }
                 }
             } else {
@@ -494,3 +722,194 @@ impl<
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn validate_watcher_messages() {
+        let alice = NetAccount::alice();
+
+        // Valid judgement request.
+        let raw = json!({
+            "event": "newJudgementRequest",
+            "data": {
+                "address": alice.as_str(),
+                "accounts": {
+                    "display_name": "Alice",
+                    "matrix": "@alice:matrix.org",
+                    "email": null,
+                },
+            },
+        })
+        .to_string();
+        match validate_watcher_message(&raw).unwrap() {
+            WatcherMessage::NewJudgementRequest(request) => {
+                assert_eq!(request.address, alice);
+                assert_eq!(
+                    request.accounts.get(&AccountType::Matrix),
+                    Some(&Some(Account::from("@alice:matrix.org")))
+                );
+            }
+            _ => panic!("unexpected message"),
+        }
+
+        // Events without judgement requests.
+        let raw = json!({
+            "event": "ack",
+            "data": {
+                "result": "Message acknowledged",
+            },
+        })
+        .to_string();
+        assert!(matches!(
+            validate_watcher_message(&raw),
+            Ok(WatcherMessage::Other(Message {
+                event: EventType::Ack,
+                ..
+            }))
+        ));
+
+        // Single entries of the pending list are validated separately.
+        let raw = json!({
+            "event": "pendingJudgementsResponse",
+            "data": [{
+                "address": alice.as_str(),
+                "accounts": {},
+            }],
+        })
+        .to_string();
+        assert!(matches!(
+            validate_watcher_message(&raw),
+            Ok(WatcherMessage::PendingJudgementsResponse(requests)) if requests.len() == 1
+        ));
+
+        let raw = json!({
+            "event": "newJudgementRequest",
+            "data": {
+                "address": "",
+                "accounts": {
+                    "matrix": "@alice:matrix.org",
+                },
+            },
+        })
+        .to_string();
+        assert!(matches!(
+            validate_watcher_message(&raw),
+            Err(ValidationError::EmptyAddress)
+        ));
+
+        let raw = json!({
+            "event": "newJudgementRequest",
+            "data": {
+                "address": alice.as_str(),
+                "accounts": {
+                    "email": null,
+                },
+            },
+        })
+        .to_string();
+        assert!(matches!(
+            validate_watcher_message(&raw),
+            Err(ValidationError::NoAccounts(_))
+        ));
+
+        // Unknown fields are ignored, so the Watcher can extend its messages.
+        let raw = json!({
+            "event": "ack",
+            "data": null,
+            "extra": true,
+        })
+        .to_string();
+        assert!(validate_watcher_message(&raw).is_ok());
+
+        // Unknown event.
+        let raw = json!({
+            "event": "unknownEvent",
+            "data": null,
+        })
+        .to_string();
+        assert!(matches!(
+            validate_watcher_message(&raw),
+            Err(ValidationError::Malformed(_))
+        ));
+
+        assert!(matches!(
+            validate_watcher_message("not json"),
+            Err(ValidationError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn connectivity_state_machine() {
+        use ConnectivityEvent::*;
+
+        let connectivity = Connectivity::default();
+        assert_eq!(
+            connectivity.state(),
+            ConnectivityState::Connecting { attempt: 1 }
+        );
+
+        connectivity.transition(ConnectionFailed("refused".to_string()));
+        assert_eq!(
+            connectivity.state(),
+            ConnectivityState::Connecting { attempt: 2 }
+        );
+
+        connectivity.transition(Connected);
+        assert!(matches!(
+            connectivity.state(),
+            ConnectivityState::Connected { .. }
+        ));
+
+        // Connection lost.
+        connectivity.transition(ConnectionFailed("connection closed".to_string()));
+        assert_eq!(
+            connectivity.state(),
+            ConnectivityState::Reconnecting {
+                attempt: 1,
+                last_error: "connection closed".to_string(),
+            }
+        );
+
+        connectivity.transition(ConnectionFailed("refused".to_string()));
+        assert_eq!(
+            connectivity.state(),
+            ConnectivityState::Reconnecting {
+                attempt: 2,
+                last_error: "refused".to_string(),
+            }
+        );
+
+        connectivity.transition(Connected);
+        assert!(matches!(
+            connectivity.state(),
+            ConnectivityState::Connected { .. }
+        ));
+
+        // Clones share the same state.
+        let handle = connectivity.clone();
+        handle.transition(GaveUp);
+        assert_eq!(connectivity.state(), ConnectivityState::Failed);
+
+        // Failed is final.
+        connectivity.transition(Connected);
+        assert_eq!(connectivity.state(), ConnectivityState::Failed);
+        connectivity.transition(ConnectionFailed("refused".to_string()));
+        assert_eq!(connectivity.state(), ConnectivityState::Failed);
+    }
+
+    #[test]
+    fn reconnect_delay_backoff() {
+        let mut delay = Duration::from_secs(0);
+        let mut delays = vec![];
+        for _ in 0..8 {
+            delay = next_reconnect_delay(delay);
+            delays.push(delay.as_secs());
+        }
+
+        assert_eq!(delays, vec![5, 10, 20, 40, 80, 160, 300, 300]);
+    }
+}
@@ -2,14 +2,28 @@ use super::Result;
 use crate::adapters::{EmailId, TwitterId};
 use crate::manager::{AccountStatus, OnChainIdentity};
 use crate::primitives::{
-    unix_time, Account, AccountType, Challenge, ChallengeStatus, NetAccount, NetworkAddress,
+    unix_time, Account, AccountType, Challenge, ChallengeStatus, Fatal, Judgement, NetAccount,
+    NetworkAddress,
 };
 use matrix_sdk::identifiers::RoomId;
-use rusqlite::{named_params, params, Connection, OptionalExtension};
+use rusqlite::{named_params, params, Connection, ErrorCode, OptionalExtension};
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::convert::TryFrom;
+use std::io::Write;
+use std::ops::{Deref, DerefMut};
 use std::result::Result as StdResult;
 use std::sync::Arc;
 use tokio::sync::Mutex;
+use tokio::time::{self, Duration};
+
+/// How long SQLite waits for a lock held by another connection before
+/// returning `SQLITE_BUSY`.
+pub const DEFAULT_BUSY_TIMEOUT: Duration = Duration::from_millis(5000);
+/// The maximum number of retries of write operations if the database is busy.
+const BUSY_MAX_RETRIES: u32 = 3;
+/// The backoff between retries, multiplied by the number of the attempt.
+const BUSY_BACKOFF: Duration = Duration::from_millis(100);
 
 #[derive(Debug, Fail)]
 pub enum DatabaseError {
@@ -29,6 +43,16 @@ impl From<rusqlite::Error> for DatabaseError {
     }
 }
 
+/// A judgement which was issued by the registrar.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct JudgementRecord {
+    pub net_account: NetAccount,
+    pub judgement: Judgement,
+    pub judged_at: u64,
+    // The amount of accounts of the identity at the time of the judgement.
+    pub field_count: u64,
+}
+
 #[derive(Clone)]
 pub struct Database {
     // Locking the connection is not ideal for performance, but since this
@@ -36,16 +60,105 @@ pub struct Database {
     // rather **one** request every few minutes/hours this is ok. It certainly
     // simplyfies database management instead of setting up a connection pool
     // with Postegres, for example.
-    con: Arc<Mutex<Connection>>,
+    con: Arc<Mutex<WalConnection>>,
+}
+
+/// The connection shared by all `Database` handles. Once the last handle is
+/// dropped, the write-ahead log is written back into the database and
+/// truncated, so the `-wal` file does not keep growing between runs.
+struct WalConnection(Connection);
+
+impl Deref for WalConnection {
+    type Target = Connection;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for WalConnection {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl Drop for WalConnection {
+    fn drop(&mut self) {
+        let _ = self
+            .0
+            .query_row("PRAGMA wal_checkpoint(TRUNCATE)", params![], |_| Ok(()))
+            .map_err(|err| {
+                error!("Failed to checkpoint database: {}", err);
+            });
+    }
+}
+
+/// Prefixes values which spreadsheet applications would interpret as a
+/// formula with a single quote. The exported accounts are user controlled.
+fn escape_csv_formula(value: &str) -> Cow<'_, str> {
+    if value.starts_with(|c: char| ['=', '+', '-', '@', '\t', '\r'].contains(&c)) {
+        Cow::Owned(format!("'{}", value))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+fn is_busy(err: &rusqlite::Error) -> bool {
+    match err {
+        rusqlite::Error::SqliteFailure(err, _) => err.code == ErrorCode::DatabaseBusy,
+        _ => false,
+    }
+}
+
+/// Executes `f` and retries it up to `max_retries` times if the database is
+/// locked by another connection (`SQLITE_BUSY`). Any other error is returned
+/// immediately. The connection is only locked for each attempt, so other
+/// tasks can use it while waiting for the next one.
+async fn with_retry<F, T>(
+    con: &Mutex<WalConnection>,
+    mut f: F,
+    max_retries: u32,
+    backoff: Duration,
+) -> rusqlite::Result<T>
+where
+    F: FnMut(&mut Connection) -> rusqlite::Result<T>,
+{
+    let mut attempt = 0;
+    loop {
+        let res = f(&mut con.lock().await.0);
+
+        match res {
+            Err(err) if is_busy(&err) && attempt < max_retries => {
+                attempt += 1;
+                warn!(
+                    "Database is busy, retrying write operation ({}/{})",
+                    attempt, max_retries
+                );
+
+                time::delay_for(backoff * attempt).await;
+            }
+            res => return res,
+        }
+    }
 }
 
 impl Database {
     pub fn new(path: &str) -> Result<Self> {
+        Self::with_busy_timeout(path, DEFAULT_BUSY_TIMEOUT)
+    }
+    pub fn with_busy_timeout(path: &str, busy_timeout: Duration) -> Result<Self> {
         let con = Connection::open(path).map_err(|err| DatabaseError::Open(err.into()))?;
+        con.busy_timeout(busy_timeout)?;
         if !con.is_autocommit() {
             return Err(failure::Error::from(DatabaseError::NoAutocommit));
         }
 
+        // The write-ahead log allows reads while writing. In WAL mode,
+        // `synchronous=NORMAL` is still safe against corruption on crashes.
+        // The pragma returns the resulting mode, which `execute` would reject.
+        con.query_row("PRAGMA journal_mode=WAL", params![], |_| Ok(()))?;
+        con.execute_batch("PRAGMA synchronous=NORMAL")?;
+
         // Table for pending identities.
         con.execute(
             "CREATE TABLE IF NOT EXISTS pending_judgments (
@@ -109,23 +222,12 @@ impl Database {
             params![],
         )?;
 
-        // TODO: This should be improved -> what if the enum adds new types?
-        con.execute(
-            "INSERT OR IGNORE INTO account_types
-                    (account_ty)
-                VALUES
-                    ('legal_name'),
-                    ('display_name'),
-                    ('email'),
-                    ('web'),
-                    ('twitter'),
-                    ('matrix'),
-                    ('pgp_fingerprint'),
-                    ('image'),
-                    ('additional')
-            ",
-            params![],
-        )?;
+        for account_ty in &AccountType::DB_TYPES {
+            con.execute(
+                "INSERT OR IGNORE INTO account_types (account_ty) VALUES (?1)",
+                params![account_ty],
+            )?;
+        }
 
         // Table for account state.
         con.execute(
@@ -156,6 +258,15 @@ impl Database {
             params![],
         )?;
 
+        // `pending_judgments.net_account` and `account_states.net_account_id`
+        // are already indexed by their UNIQUE constraints. Lookups by the
+        // account itself (e.g. on incoming messages) are not.
+        con.execute(
+            "CREATE INDEX IF NOT EXISTS idx_account_states_account
+                ON account_states (account)",
+            params![],
+        )?;
+
         // Table for known Matrix rooms.
         con.execute(
             "CREATE TABLE IF NOT EXISTS known_matrix_rooms (
@@ -271,19 +382,118 @@ impl Database {
             params![],
         )?;
 
+        // Judgements which were submitted to the Watcher, but not yet
+        // acknowledged. Keeps the amount of accounts, since the identity is
+        // removed before the acknowledgement arrives.
+        con.execute(
+            "
+            CREATE TABLE IF NOT EXISTS submitted_judgements (
+                id           INTEGER PRIMARY KEY,
+                net_account  TEXT NOT NULL UNIQUE,
+                judgement    TEXT NOT NULL,
+                field_count  INTEGER NOT NULL
+            )
+        ",
+            params![],
+        )?;
+
+        // Log of issued judgements. Not linked to `pending_judgments`, since
+        // identities are removed once the judgement was given.
+        con.execute(
+            "
+            CREATE TABLE IF NOT EXISTS judgements_log (
+                id           INTEGER PRIMARY KEY,
+                net_account  TEXT NOT NULL,
+                judgement    TEXT NOT NULL,
+                judged_at    INTEGER NOT NULL,
+                field_count  INTEGER NOT NULL
+            )
+        ",
+            params![],
+        )?;
+
+        // Identities for which verification was halted by an operator. Not
+        // linked to `pending_judgments`, so the freeze survives re-insertions
+        // of the judgement request.
+        con.execute(
+            "
+            CREATE TABLE IF NOT EXISTS frozen_identities (
+                id           INTEGER PRIMARY KEY,
+                net_account  TEXT NOT NULL UNIQUE,
+                frozen_at    INTEGER NOT NULL
+            )
+        ",
+            params![],
+        )?;
+
         Ok(Database {
-            con: Arc::new(Mutex::new(con)),
+            con: Arc::new(Mutex::new(WalConnection(con))),
         })
     }
+    /// Counts the accounts which still need to be verified, grouped by
+    /// account type.
+    pub async fn count_pending_by_account_type(&self) -> Result<HashMap<AccountType, u64>> {
+        let con = self.con.lock().await;
+        let mut stmt = con.prepare(
+            "
+            SELECT
+                account_ty, COUNT(*)
+            FROM
+                account_states
+            INNER JOIN
+                account_types
+            ON
+                account_states.account_ty_id = account_types.id
+            WHERE
+                account_states.challenge_status_id != (
+                    SELECT
+                        id
+                    FROM
+                        challenge_status
+                    WHERE
+                        status = 'accepted'
+                )
+            GROUP BY
+                account_ty
+        ",
+        )?;
+
+        let mut rows = stmt.query(params![])?;
+
+        let mut counts = HashMap::new();
+        while let Some(row) = rows.next()? {
+            counts.insert(row.get::<_, AccountType>(0)?, row.get::<_, i64>(1)? as u64);
+        }
+
+        Ok(counts)
+    }
     pub async fn insert_identity(&self, ident: &OnChainIdentity) -> Result<()> {
         self.insert_identity_batch(&[ident]).await
     }
+    /// Inserts the identities and their account states. This is idempotent,
+    /// since the Watcher may deliver the same judgement request multiple
+    /// times: an existing judgement request is kept (including its creation
+    /// time) and the account state of each account type is replaced.
     pub async fn insert_identity_batch(&self, idents: &[&OnChainIdentity]) -> Result<()> {
-        let mut con = self.con.lock().await;
+        with_retry(
+            &self.con,
+            |con| Self::insert_identity_batch_tx(con, idents),
+            BUSY_MAX_RETRIES,
+            BUSY_BACKOFF,
+        )
+        .await
+        .map_err(|err| err.into())
+    }
+    // Write statements use the statement cache of the connection, so the SQL
+    // is compiled only once instead of on every call.
+    fn insert_identity_batch_tx(
+        con: &mut Connection,
+        idents: &[&OnChainIdentity],
+    ) -> rusqlite::Result<()> {
         let transaction = con.transaction()?;
 
         {
-            let mut stmt = transaction.prepare(
+            let mut stmt = transaction.prepare_cached(
                 "INSERT OR IGNORE INTO pending_judgments (
                     net_account,
                     created
@@ -301,7 +511,7 @@ impl Database {
                 })?;
             }
 
-            let mut stmt = transaction.prepare(
+            let mut stmt = transaction.prepare_cached(
                 "
                 INSERT OR REPLACE INTO account_states (
                     net_account_id,
@@ -348,7 +558,7 @@ impl Database {
                 )",
             )?;
 
-            let mut stmt_intro = transaction.prepare(
+            let mut stmt_intro = transaction.prepare_cached(
                 "
                 INSERT OR IGNORE INTO intro_msg_sent (
                     account,
@@ -487,6 +697,50 @@ impl Database {
         .optional()
         .map_err(|err| err.into())
     }
+    /// Returns the already issued challenge of the specified account, so it
+    /// can be sent again without generating a new one.
+    pub async fn select_challenge(
+        &self,
+        net_account: &NetAccount,
+        account_ty: &AccountType,
+    ) -> Result<Option<Challenge>> {
+        let con = self.con.lock().await;
+
+        con.query_row_named(
+            "
+            SELECT
+                challenge
+            FROM
+                account_states
+            WHERE
+                net_account_id = (
+                    SELECT
+                        id
+                    FROM
+                        pending_judgments
+                    WHERE
+                        net_account = :net_account
+                )
+            AND
+                account_ty_id = (
+                    SELECT
+                        id
+                    FROM
+                        account_types
+                    WHERE
+                        account_ty = :account_ty
+                )
+        ",
+            named_params! {
+                ":net_account": net_account,
+                ":account_ty": account_ty,
+            },
+            |row| row.get::<_, String>(0),
+        )
+        .optional()
+        .map(|challenge| challenge.map(Challenge))
+        .map_err(|err| err.into())
+    }
     #[cfg(test)]
     async fn select_identities(&self) -> Result<Vec<OnChainIdentity>> {
         let con = self.con.lock().await;
@@ -516,7 +770,13 @@ impl Database {
             {
                 ident.push_account(account_ty, row.get::<_, Account>(2)?)?;
             } else {
-                let mut ident = OnChainIdentity::new(net_account)?;
+                let mut ident = match OnChainIdentity::new(net_account.clone()) {
+                    Ok(ident) => ident,
+                    Err(err) => {
+                        warn!("Skipping stored identity {}: {}", net_account, err);
+                        continue;
+                    }
+                };
                 ident.push_account(account_ty, row.get::<_, Account>(2)?)?;
 
                 idents.push(ident);
@@ -525,21 +785,172 @@ impl Database {
 
         Ok(idents)
     }
+    /// Returns the pending identity including the current state of each
+    /// account, if any.
+    pub async fn select_identity(
+        &self,
+        net_account: &NetAccount,
+    ) -> Result<Option<OnChainIdentity>> {
+        let con = self.con.lock().await;
+        let mut stmt = con.prepare(
+            "
+            SELECT
+                account_ty,
+                account,
+                account_status.status,
+                challenge,
+                challenge_status.status
+            FROM
+                pending_judgments
+            LEFT JOIN account_states
+                ON pending_judgments.id = account_states.net_account_id
+            LEFT JOIN account_types
+                ON account_states.account_ty_id = account_types.id
+            LEFT JOIN account_status
+                ON account_states.account_status_id = account_status.id
+            LEFT JOIN challenge_status
+                ON account_states.challenge_status_id = challenge_status.id
+            WHERE
+                pending_judgments.net_account = :net_account
+        ",
+        )?;
+
+        let mut ident: Option<OnChainIdentity> = None;
+
+        let mut rows = stmt.query_named(named_params! {
+            ":net_account": net_account,
+        })?;
+
+        while let Some(row) = rows.next()? {
+            let ident = match ident {
+                Some(ref mut ident) => ident,
+                None => match OnChainIdentity::new(net_account.clone()) {
+                    Ok(new) => ident.get_or_insert(new),
+                    Err(err) => {
+                        warn!("Skipping stored identity {}: {}", net_account, err);
+                        return Ok(None);
+                    }
+                },
+            };
+
+            // Identities without any accounts.
+            let account_ty = match row.get::<_, Option<AccountType>>(0)? {
+                Some(account_ty) => account_ty,
+                None => continue,
+            };
+
+            ident.push_account(account_ty, row.get::<_, Account>(1)?)?;
+
+            if let Some(state) = ident.account_states_mut().last_mut() {
+                state.account_status = row.get::<_, AccountStatus>(2)?;
+                state.challenge = Challenge(row.get::<_, String>(3)?);
+                state.challenge_status = row.get::<_, ChallengeStatus>(4)?;
+            }
+        }
+
+        Ok(ident)
+    }
+    /// Writes all pending identities as CSV, one row per account, for manual
+    /// audits by operators.
+    pub async fn export_snapshot_csv<W: Write>(&self, writer: W) -> Result<()> {
+        let records = {
+            let con = self.con.lock().await;
+            let mut stmt = con.prepare(
+                "
+                SELECT
+                    net_account,
+                    account_ty,
+                    account,
+                    challenge_status.status,
+                    account_status.status,
+                    created
+                FROM
+                    pending_judgments
+                INNER JOIN account_states
+                    ON pending_judgments.id = account_states.net_account_id
+                INNER JOIN account_types
+                    ON account_states.account_ty_id = account_types.id
+                INNER JOIN account_status
+                    ON account_states.account_status_id = account_status.id
+                INNER JOIN challenge_status
+                    ON account_states.challenge_status_id = challenge_status.id
+                ORDER BY
+                    pending_judgments.id, account_types.id
+            ",
+            )?;
+
+            let mut rows = stmt.query(params![])?;
+
+            let mut records = vec![];
+            while let Some(row) = rows.next()? {
+                records.push((
+                    row.get::<_, NetAccount>(0)?,
+                    row.get::<_, AccountType>(1)?,
+                    row.get::<_, Account>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, String>(4)?,
+                    row.get::<_, i64>(5)?,
+                ));
+            }
+
+            records
+        };
+
+        let mut writer = csv::Writer::from_writer(writer);
+        writer.write_record(&[
+            "network",
+            "address",
+            "field_type",
+            "field_address",
+            "challenge_status",
+            "validity",
+            "issued_at",
+        ])?;
+
+        for (net_account, account_ty, account, challenge_status, account_status, created) in records
+        {
+            let network = NetworkAddress::try_from(net_account.clone())
+                .map(|address| address.address_format().as_str())
+                .unwrap_or("unknown");
+
+            writer.write_record(&[
+                network,
+                &escape_csv_formula(net_account.as_str()),
+                account_ty.as_db_str().fatal(),
+                &escape_csv_formula(account.as_str()),
+                &challenge_status,
+                &account_status,
+                &created.to_string(),
+            ])?;
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
     // TODO: Should be account instead of net_account.
     pub async fn insert_room_id(&self, net_account: &NetAccount, room_id: &RoomId) -> Result<()> {
-        self.con.lock().await.execute_named(
-            "INSERT OR REPLACE INTO known_matrix_rooms (
-                    net_account_id,
-                    room_id
-                ) VALUES (
-                    (SELECT id FROM pending_judgments WHERE net_account = :net_account),
-                    :room_id
-                )",
-            named_params! {
-                ":net_account": net_account,
-                ":room_id": room_id.as_str(),
+        with_retry(
+            &self.con,
+            |con| {
+                con.execute_named(
+                    "INSERT OR REPLACE INTO known_matrix_rooms (
+                        net_account_id,
+                        room_id
+                    ) VALUES (
+                        (SELECT id FROM pending_judgments WHERE net_account = :net_account),
+                        :room_id
+                    )",
+                    named_params! {
+                        ":net_account": net_account,
+                        ":room_id": room_id.as_str(),
+                    },
+                )
             },
-        )?;
+            BUSY_MAX_RETRIES,
+            BUSY_BACKOFF,
+        )
+        .await?;
 
         Ok(())
     }
@@ -584,67 +995,169 @@ impl Database {
 
         Ok(room_ids)
     }
-    // TODO: Should not require `NetAccount`.
-    pub async fn set_account_status(
+    /// Returns the address of the pending judgement the room belongs to, if
+    /// any.
+    pub async fn select_net_account_by_room_id(
         &self,
-        account: &Account,
-        account_ty: &AccountType,
-        status: &AccountStatus,
-    ) -> StdResult<(), DatabaseError> {
+        room_id: &RoomId,
+    ) -> Result<Option<NetAccount>> {
         let con = self.con.lock().await;
-
-        con.execute_named(
-            "UPDATE
-                    account_states
-                SET
-                    account_status_id = (
-                        SELECT
-                            id
-                        FROM
-                            account_status
-                        WHERE
-                            status = :account_status
-                    )
+        con.query_row_named(
+            "SELECT net_account
+                FROM known_matrix_rooms
+                INNER JOIN pending_judgments
+                    ON known_matrix_rooms.net_account_id = pending_judgments.id
                 WHERE
-                    account = :account
-                AND
-                    account_ty_id = (
-                        SELECT
-                            id
-                        FROM
-                            account_types
-                        WHERE
-                        account_ty = :account_ty
-                    )
-            ",
+                    room_id = :room_id
+                ",
             named_params! {
-                ":account": account,
-                ":account_status": status,
-                ":account_ty": account_ty,
+                ":room_id": room_id.as_str(),
             },
+            |row| row.get::<_, NetAccount>(0),
         )
+        .optional()
         .map_err(|err| err.into())
-        .and_then(|changes| {
-            if changes == 0 {
-                Err(DatabaseError::NoChange)
-            } else {
-                Ok(changes)
-            }
-        })?;
-
-        Ok(())
     }
-    pub async fn set_challenge_status(
-        &self,
-        net_account: &NetAccount,
-        account_ty: &AccountType,
-        status: &ChallengeStatus,
-    ) -> Result<()> {
-        self.con.lock().await.execute_named(
-            "UPDATE
-                    account_states
-                SET
-                    challenge_status_id = (
+    /// Returns all known rooms, including the address of the pending judgement
+    /// they belong to. Rooms of identities which no longer exist have no
+    /// address.
+    pub async fn select_rooms(&self) -> Result<Vec<(RoomId, Option<NetAccount>)>> {
+        let con = self.con.lock().await;
+        let mut stmt = con.prepare(
+            "
+            SELECT
+                room_id, net_account
+            FROM
+                known_matrix_rooms
+            LEFT JOIN pending_judgments
+                ON known_matrix_rooms.net_account_id = pending_judgments.id
+            WHERE
+                room_id IS NOT NULL
+        ",
+        )?;
+
+        let mut rows = stmt.query(params![])?;
+
+        let mut rooms = vec![];
+        while let Some(row) = rows.next()? {
+            rooms.push((
+                RoomId::try_from(row.get::<_, String>(0)?)?,
+                row.get::<_, Option<NetAccount>>(1)?,
+            ));
+        }
+
+        Ok(rooms)
+    }
+    /// Returns all rooms which are no longer associated with a pending
+    /// judgement, e.g. after the identity was removed.
+    pub async fn select_orphaned_room_ids(&self) -> Result<Vec<RoomId>> {
+        let con = self.con.lock().await;
+        let mut stmt = con.prepare(
+            "
+            SELECT
+                room_id
+            FROM
+                known_matrix_rooms
+            WHERE
+                net_account_id NOT IN (
+                    SELECT
+                        id
+                    FROM
+                        pending_judgments
+                )
+            AND
+                room_id IS NOT NULL
+        ",
+        )?;
+
+        let mut rows = stmt.query(params![])?;
+
+        let mut room_ids = vec![];
+        while let Some(row) = rows.next()? {
+            room_ids.push(RoomId::try_from(row.get::<_, String>(0)?)?);
+        }
+
+        Ok(room_ids)
+    }
+    /// Forgets about the room, e.g. after it was left.
+    pub async fn delete_room_id(&self, room_id: &RoomId) -> Result<()> {
+        let con = self.con.lock().await;
+        con.execute_named(
+            "DELETE FROM known_matrix_rooms WHERE room_id = :room_id",
+            named_params! {
+                ":room_id": room_id.as_str(),
+            },
+        )?;
+
+        Ok(())
+    }
+    // TODO: Should not require `NetAccount`.
+    pub async fn set_account_status(
+        &self,
+        account: &Account,
+        account_ty: &AccountType,
+        status: &AccountStatus,
+    ) -> StdResult<(), DatabaseError> {
+        with_retry(
+            &self.con,
+            |con| {
+                con.execute_named(
+                    "UPDATE
+                            account_states
+                        SET
+                            account_status_id = (
+                                SELECT
+                                    id
+                                FROM
+                                    account_status
+                                WHERE
+                                    status = :account_status
+                            )
+                        WHERE
+                            account = :account
+                        AND
+                            account_ty_id = (
+                                SELECT
+                                    id
+                                FROM
+                                    account_types
+                                WHERE
+                                account_ty = :account_ty
+                            )
+                    ",
+                    named_params! {
+                        ":account": account,
+                        ":account_status": status,
+                        ":account_ty": account_ty,
+                    },
+                )
+            },
+            BUSY_MAX_RETRIES,
+            BUSY_BACKOFF,
+        )
+        .await
+        .map_err(|err| err.into())
+        .and_then(|changes| {
+            if changes == 0 {
+                Err(DatabaseError::NoChange)
+            } else {
+                Ok(changes)
+            }
+        })?;
+
+        Ok(())
+    }
+    pub async fn set_challenge_status(
+        &self,
+        net_account: &NetAccount,
+        account_ty: &AccountType,
+        status: &ChallengeStatus,
+    ) -> Result<()> {
+        self.con.lock().await.execute_named(
+            "UPDATE
+                    account_states
+                SET
+                    challenge_status_id = (
                         SELECT
                             id
                         FROM
@@ -727,10 +1240,21 @@ impl Database {
 
         let mut challenge_set = vec![];
         while let Some(row) = rows.next()? {
-            challenge_set.push((
-                NetworkAddress::try_from(row.get::<_, NetAccount>(0)?)?,
-                Challenge(row.get::<_, String>(1)?),
-            ));
+            // A single stored address which fails to parse (e.g. stored
+            // before its format was validated) must not block the others.
+            let net_account = row.get::<_, NetAccount>(0)?;
+            let network_address = match NetworkAddress::try_from(net_account.clone()) {
+                Ok(network_address) => network_address,
+                Err(err) => {
+                    warn!(
+                        "Skipping challenge of stored identity {}: {}",
+                        net_account, err
+                    );
+                    continue;
+                }
+            };
+
+            challenge_set.push((network_address, Challenge(row.get::<_, String>(1)?)));
         }
 
         // If the introduction message was already sent to the **account**,
@@ -883,20 +1407,180 @@ impl Database {
 
         Ok(net_accounts)
     }
-    pub async fn delete_identity(&self, net_account: &NetAccount) -> Result<()> {
+    /// Returns the Unix timestamp of when the judgement request of the
+    /// identity was received, if the identity is pending.
+    pub async fn select_pending_since(&self, net_account: &NetAccount) -> Result<Option<u64>> {
         let con = self.con.lock().await;
-        con.execute_named(
+        con.query_row_named(
+            "SELECT created FROM pending_judgments WHERE net_account = :net_account",
+            named_params! {
+                ":net_account": net_account,
+            },
+            |row| row.get::<_, i64>(0),
+        )
+        .optional()
+        .map(|created| created.map(|created| created as u64))
+        .map_err(|err| err.into())
+    }
+    /// Returns all pending identities together with the time (in seconds)
+    /// since the judgement request was received, longest pending first.
+    pub async fn select_pending_by_age(&self) -> Result<Vec<(NetAccount, u64)>> {
+        let con = self.con.lock().await;
+        let mut stmt = con.prepare(
             "
-            DELETE FROM
+            SELECT
+                net_account, created
+            FROM
                 pending_judgments
-            WHERE
-                net_account = :net_account
-            ",
+            ORDER BY
+                created ASC, id ASC
+        ",
+        )?;
+
+        let mut rows = stmt.query(params![])?;
+
+        let now = unix_time();
+        let mut pending = vec![];
+        while let Some(row) = rows.next()? {
+            pending.push((
+                row.get::<_, NetAccount>(0)?,
+                now.saturating_sub(row.get::<_, i64>(1)? as u64),
+            ));
+        }
+
+        Ok(pending)
+    }
+    /// Halts the verification of the identity until it gets unfrozen.
+    /// Responses to challenges are ignored and no judgement is issued.
+    pub async fn freeze_identity(&self, net_account: &NetAccount) -> Result<()> {
+        let con = self.con.lock().await;
+
+        con.execute_named(
+            "
+            INSERT OR IGNORE INTO frozen_identities (
+                net_account,
+                frozen_at
+            ) VALUES (
+                :net_account,
+                :timestamp
+            )
+        ",
+            named_params! {
+                ":net_account": net_account,
+                ":timestamp": unix_time() as i64,
+            },
+        )?;
+
+        Ok(())
+    }
+    pub async fn unfreeze_identity(&self, net_account: &NetAccount) -> Result<()> {
+        let con = self.con.lock().await;
+
+        con.execute_named(
+            "DELETE FROM frozen_identities WHERE net_account = :net_account",
+            named_params! {
+                ":net_account": net_account,
+            },
+        )?;
+
+        Ok(())
+    }
+    pub async fn is_frozen(&self, net_account: &NetAccount) -> Result<bool> {
+        let con = self.con.lock().await;
+
+        con.query_row_named(
+            "SELECT id FROM frozen_identities WHERE net_account = :net_account",
             named_params! {
-                ":net_account": net_account
+                ":net_account": net_account,
             },
+            |_| Ok(()),
+        )
+        .optional()
+        .map(|frozen| frozen.is_some())
+        .map_err(|err| err.into())
+    }
+    /// Returns the accounts whose challenge was not accepted yet, together
+    /// with the creation time of the judgement request. Invalid and
+    /// unsupported accounts as well as frozen identities are excluded.
+    pub async fn select_pending_challenges(&self) -> Result<Vec<(NetAccount, AccountType, u64)>> {
+        let con = self.con.lock().await;
+
+        let mut stmt = con.prepare(
+            "
+            SELECT
+                net_account, account_ty, created
+            FROM
+                pending_judgments
+            INNER JOIN
+                account_states
+            ON
+                pending_judgments.id = account_states.net_account_id
+            INNER JOIN
+                account_types
+            ON
+                account_states.account_ty_id = account_types.id
+            WHERE
+                account_states.challenge_status_id != (
+                    SELECT
+                        id
+                    FROM
+                        challenge_status
+                    WHERE
+                        status = 'accepted'
+                )
+            AND
+                account_states.account_status_id NOT IN (
+                    SELECT
+                        id
+                    FROM
+                        account_status
+                    WHERE
+                        status IN ('invalid', 'unsupported')
+                )
+            AND
+                pending_judgments.net_account NOT IN (
+                    SELECT
+                        net_account
+                    FROM
+                        frozen_identities
+                )
+        ",
         )?;
 
+        let mut rows = stmt.query(params![])?;
+
+        let mut pending = vec![];
+        while let Some(row) = rows.next()? {
+            pending.push((
+                row.get::<_, NetAccount>(0)?,
+                row.get::<_, AccountType>(1)?,
+                row.get::<_, i64>(2)? as u64,
+            ));
+        }
+
+        Ok(pending)
+    }
+    pub async fn delete_identity(&self, net_account: &NetAccount) -> Result<()> {
+        with_retry(
+            &self.con,
+            |con| {
+                con.execute_named(
+                    "
+                    DELETE FROM
+                        pending_judgments
+                    WHERE
+                        net_account = :net_account
+                    ",
+                    named_params! {
+                        ":net_account": net_account
+                    },
+                )
+            },
+            BUSY_MAX_RETRIES,
+            BUSY_BACKOFF,
+        )
+        .await?;
+
         Ok(())
     }
     pub async fn delete_account(
@@ -1004,7 +1688,7 @@ impl Database {
     pub async fn insert_twitter_ids(&self, pair: &[(&Account, &TwitterId)]) -> Result<()> {
         let con = self.con.lock().await;
 
-        let mut lookup_stmt = con.prepare(
+        let mut lookup_stmt = con.prepare_cached(
             "SELECT
                     id
                 FROM
@@ -1014,7 +1698,7 @@ impl Database {
                 ",
         )?;
 
-        let mut stmt = con.prepare(
+        let mut stmt = con.prepare_cached(
             "
             INSERT OR REPLACE INTO
                 known_twitter_ids (
@@ -1272,25 +1956,38 @@ impl Database {
         net_account: &NetAccount,
         account: &Account,
     ) -> Result<()> {
-        let con = self.con.lock().await;
+        self.insert_display_names(&[(account, net_account)]).await
+    }
+    /// Inserts all display names in a single transaction. Used on startup,
+    /// where all existing display names are fetched from the Watcher.
+    pub async fn insert_display_names(&self, pair: &[(&Account, &NetAccount)]) -> Result<()> {
+        let mut con = self.con.lock().await;
+        let transaction = con.transaction()?;
 
-        con.execute_named(
-            "
-            INSERT OR REPLACE INTO display_names (
-                name,
-                net_account,
-                persist
-            ) VALUES (
-                :account,
-                :net_account,
-                '0'
-            )
-        ",
-            named_params! {
-                ":account": account,
-                ":net_account": net_account,
-            },
-        )?;
+        {
+            let mut stmt = transaction.prepare_cached(
+                "
+                INSERT OR REPLACE INTO display_names (
+                    name,
+                    net_account,
+                    persist
+                ) VALUES (
+                    :account,
+                    :net_account,
+                    '0'
+                )
+            ",
+            )?;
+
+            for (account, net_account) in pair {
+                stmt.execute_named(named_params! {
+                    ":account": account,
+                    ":net_account": net_account,
+                })?;
+            }
+        }
+
+        transaction.commit()?;
 
         Ok(())
     }
@@ -1345,7 +2042,7 @@ impl Database {
     ) -> Result<()> {
         let con = self.con.lock().await;
 
-        let mut stmt = con.prepare(
+        let mut stmt = con.prepare_cached(
             "
             INSERT OR IGNORE INTO display_name_violations (
                 name,
@@ -1436,15 +2133,158 @@ impl Database {
         .map_err(|err| err.into())
         .map(|_| ())
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::adapters::{EmailId, TwitterId};
-    use crate::primitives::{Challenge, NetAccount};
-    use tokio::runtime::Runtime;
-    use tokio::time::{self, Duration};
+    /// Remembers the judgement submitted to the Watcher, including the amount
+    /// of accounts the identity currently has. The judgement is logged once
+    /// the Watcher acknowledges it, see `insert_judgement_log`.
+    pub async fn insert_submitted_judgement(
+        &self,
+        net_account: &NetAccount,
+        judgement: &Judgement,
+    ) -> Result<()> {
+        let con = self.con.lock().await;
+
+        con.execute_named(
+            "
+            INSERT OR REPLACE INTO submitted_judgements (
+                net_account,
+                judgement,
+                field_count
+            ) VALUES (
+                :net_account,
+                :judgement,
+                (
+                    SELECT
+                        COUNT(*)
+                    FROM
+                        account_states
+                    WHERE
+                        net_account_id = (
+                            SELECT
+                                id
+                            FROM
+                                pending_judgments
+                            WHERE
+                                net_account = :net_account
+                        )
+                )
+            )
+        ",
+            named_params! {
+                ":net_account": net_account,
+                ":judgement": judgement,
+            },
+        )?;
+
+        Ok(())
+    }
+    /// Logs the submitted judgement of the identity as issued. Returns `false`
+    /// if no judgement was submitted for the identity.
+    pub async fn insert_judgement_log(&self, net_account: &NetAccount) -> Result<bool> {
+        let mut con = self.con.lock().await;
+        let transaction = con.transaction()?;
+
+        let inserted = transaction.execute_named(
+            "
+            INSERT INTO judgements_log (
+                net_account,
+                judgement,
+                judged_at,
+                field_count
+            )
+            SELECT
+                net_account, judgement, :timestamp, field_count
+            FROM
+                submitted_judgements
+            WHERE
+                net_account = :net_account
+        ",
+            named_params! {
+                ":net_account": net_account,
+                ":timestamp": unix_time() as i64,
+            },
+        )?;
+
+        transaction.execute_named(
+            "DELETE FROM submitted_judgements WHERE net_account = :net_account",
+            named_params! {
+                ":net_account": net_account,
+            },
+        )?;
+
+        transaction.commit()?;
+
+        Ok(inserted > 0)
+    }
+    /// Returns the most recent judgements, newest first. Optionally only
+    /// includes judgements issued at or after the `since` Unix timestamp.
+    pub async fn select_recent_judgements(
+        &self,
+        limit: u32,
+        since: Option<u64>,
+    ) -> Result<Vec<JudgementRecord>> {
+        let con = self.con.lock().await;
+
+        let mut stmt = con.prepare(
+            "
+            SELECT
+                net_account, judgement, judged_at, field_count
+            FROM
+                judgements_log
+            WHERE
+                judged_at >= :since
+            ORDER BY
+                judged_at DESC, id DESC
+            LIMIT
+                :limit
+        ",
+        )?;
+
+        let mut rows = stmt.query_named(named_params! {
+            ":since": since.unwrap_or(0) as i64,
+            ":limit": limit,
+        })?;
+
+        let mut records = vec![];
+        while let Some(row) = rows.next()? {
+            records.push(JudgementRecord {
+                net_account: row.get::<_, NetAccount>(0)?,
+                judgement: row.get::<_, Judgement>(1)?,
+                judged_at: row.get::<_, i64>(2)? as u64,
+                field_count: row.get::<_, i64>(3)? as u64,
+            });
+        }
+
+        Ok(records)
+    }
+    /// Rebuilds the database file, reclaiming the space of deleted entries.
+    /// Note that SQLite cannot vacuum from within a transaction, so this
+    /// requires the connection to be in auto-commit mode.
+    pub async fn vacuum(&self) -> Result<()> {
+        let con = self.con.lock().await;
+        if !con.is_autocommit() {
+            return Err(DatabaseError::NoAutocommit.into());
+        }
+
+        con.execute_batch("VACUUM").map_err(|err| err.into())
+    }
+    /// Writes the content of the write-ahead log back into the database and
+    /// truncates the log file. This has no effect if WAL mode is not enabled.
+    pub async fn wal_checkpoint(&self) -> Result<()> {
+        let con = self.con.lock().await;
+
+        // The pragma returns a status row, which `execute` would reject.
+        con.query_row("PRAGMA wal_checkpoint(TRUNCATE)", params![], |_| Ok(()))
+            .map_err(|err| err.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapters::{EmailId, TwitterId};
+    use crate::primitives::{Challenge, NetAccount};
+    use tokio::runtime::Runtime;
+    use tokio::time::{self, Duration};
 
     // Generate a random db path
     fn db_path() -> String {
@@ -1460,6 +2300,319 @@ mod tests {
         let _db = Database::new(&path).unwrap();
     }
 
+    #[test]
+    fn recent_judgements() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let db = Database::new(&db_path()).unwrap();
+
+            let res = db.select_recent_judgements(10, None).await.unwrap();
+            assert!(res.is_empty());
+
+            let alice = NetAccount::alice();
+            let bob = NetAccount::bob();
+
+            let mut ident = OnChainIdentity::new(alice.clone()).unwrap();
+            ident
+                .push_account(AccountType::Matrix, Account::from("@alice:matrix.org"))
+                .unwrap();
+            ident
+                .push_account(AccountType::Email, Account::from("alice@example.com"))
+                .unwrap();
+            db.insert_identity(&ident).await.unwrap();
+
+            let mut ident = OnChainIdentity::new(bob.clone()).unwrap();
+            ident
+                .push_account(AccountType::Matrix, Account::from("@bob:matrix.org"))
+                .unwrap();
+            db.insert_identity(&ident).await.unwrap();
+
+            let start = unix_time();
+
+            db.insert_submitted_judgement(&alice, &Judgement::Reasonable)
+                .await
+                .unwrap();
+            db.insert_submitted_judgement(&bob, &Judgement::Erroneous)
+                .await
+                .unwrap();
+
+            // Not logged until acknowledged by the Watcher.
+            let res = db.select_recent_judgements(10, None).await.unwrap();
+            assert!(res.is_empty());
+
+            // The log survives the removal of the identity.
+            db.delete_identity(&alice).await.unwrap();
+
+            assert!(db.insert_judgement_log(&alice).await.unwrap());
+            assert!(db.insert_judgement_log(&bob).await.unwrap());
+
+            // Acknowledgements are only logged once.
+            assert!(!db.insert_judgement_log(&bob).await.unwrap());
+            assert!(!db.insert_judgement_log(&NetAccount::eve()).await.unwrap());
+
+            let res = db.select_recent_judgements(10, None).await.unwrap();
+            assert_eq!(res.len(), 2);
+
+            // Newest first.
+            assert_eq!(res[0].net_account, bob);
+            assert_eq!(res[0].judgement, Judgement::Erroneous);
+            assert_eq!(res[0].field_count, 1);
+            assert_eq!(res[1].net_account, alice);
+            assert_eq!(res[1].judgement, Judgement::Reasonable);
+            assert_eq!(res[1].field_count, 2);
+            assert!(res[1].judged_at >= start);
+
+            let res = db.select_recent_judgements(1, None).await.unwrap();
+            assert_eq!(res.len(), 1);
+            assert_eq!(res[0].net_account, bob);
+
+            let res = db
+                .select_recent_judgements(10, Some(start + 3600))
+                .await
+                .unwrap();
+            assert!(res.is_empty());
+        });
+    }
+
+    #[test]
+    fn retry_on_busy_database() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let path = db_path();
+            let db = Database::with_busy_timeout(&path, Duration::from_millis(0)).unwrap();
+
+            let alice = NetAccount::alice();
+            let mut ident = OnChainIdentity::new(alice.clone()).unwrap();
+            ident
+                .push_account(AccountType::Matrix, Account::from("@alice:matrix.org"))
+                .unwrap();
+            db.insert_identity(&ident).await.unwrap();
+
+            let res = db.select_account_statuses(&alice).await.unwrap();
+            assert_eq!(res.len(), 1);
+
+            // Lock the database with a second connection.
+            let other = Connection::open(&path).unwrap();
+            other.execute_batch("BEGIN EXCLUSIVE").unwrap();
+
+            let handle = {
+                let db = db.clone();
+                let alice = alice.clone();
+                tokio::spawn(async move { db.delete_identity(&alice).await })
+            };
+
+            // The connection is not locked while waiting for the next attempt.
+            time::delay_for(BUSY_BACKOFF / 2).await;
+            assert!(db.con.try_lock().is_ok());
+
+            // Gives up after the maximum amount of retries.
+            let err = handle.await.unwrap().unwrap_err();
+            assert!(is_busy(err.downcast_ref::<rusqlite::Error>().unwrap()));
+
+            // Release the lock while the operation is being retried.
+            let handle = std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(50));
+                other.execute_batch("COMMIT").unwrap();
+            });
+
+            db.delete_identity(&alice).await.unwrap();
+            handle.join().unwrap();
+
+            let res = db.select_account_statuses(&alice).await.unwrap();
+            assert!(res.is_empty());
+        });
+    }
+
+    #[test]
+    fn journal_mode_wal() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let path = db_path();
+            let db = Database::new(&path).unwrap();
+
+            let con = db.con.lock().await;
+            let mode = con
+                .query_row("PRAGMA journal_mode", params![], |row| {
+                    row.get::<_, String>(0)
+                })
+                .unwrap();
+            assert_eq!(mode, "wal");
+
+            // NORMAL
+            let sync = con
+                .query_row("PRAGMA synchronous", params![], |row| row.get::<_, i64>(0))
+                .unwrap();
+            assert_eq!(sync, 1);
+            drop(con);
+
+            // The journal mode is persistent, opening the database again works.
+            let db = Database::new(&path).unwrap();
+            let con = db.con.lock().await;
+            let mode = con
+                .query_row("PRAGMA journal_mode", params![], |row| {
+                    row.get::<_, String>(0)
+                })
+                .unwrap();
+            assert_eq!(mode, "wal");
+        });
+    }
+
+    #[test]
+    fn vacuum_and_wal_checkpoint() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let db = Database::new(&db_path()).unwrap();
+
+            let alice = NetAccount::alice();
+            let bob = NetAccount::bob();
+
+            let mut ident = OnChainIdentity::new(alice.clone()).unwrap();
+            ident
+                .push_account(AccountType::Matrix, Account::from("@alice:matrix.org"))
+                .unwrap();
+            db.insert_identity(&ident).await.unwrap();
+
+            let mut ident = OnChainIdentity::new(bob.clone()).unwrap();
+            ident
+                .push_account(AccountType::Matrix, Account::from("@bob:matrix.org"))
+                .unwrap();
+            db.insert_identity(&ident).await.unwrap();
+
+            db.delete_identity(&bob).await.unwrap();
+
+            db.vacuum().await.unwrap();
+            db.wal_checkpoint().await.unwrap();
+
+            // Remaining entries are untouched.
+            let res = db
+                .select_challenge(&alice, &AccountType::Matrix)
+                .await
+                .unwrap();
+            assert_eq!(res, Some(Challenge::gen_fixed()));
+
+            let res = db
+                .select_challenge(&bob, &AccountType::Matrix)
+                .await
+                .unwrap();
+            assert!(res.is_none());
+        });
+    }
+
+    #[test]
+    fn wal_checkpoint_on_drop() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let path = db_path();
+            let wal_path = format!("{}-wal", path);
+
+            let db = Database::new(&path).unwrap();
+            let ident = OnChainIdentity::new(NetAccount::alice()).unwrap();
+            db.insert_identity(&ident).await.unwrap();
+
+            // Keeps SQLite from checkpointing the log on its own when the
+            // database is closed.
+            let other = Connection::open(&path).unwrap();
+            other
+                .query_row("SELECT COUNT(*) FROM pending_judgments", params![], |_| {
+                    Ok(())
+                })
+                .unwrap();
+
+            assert!(std::fs::metadata(&wal_path).unwrap().len() > 0);
+
+            // Only the last handle checkpoints.
+            let handle = db.clone();
+            drop(db);
+            assert!(std::fs::metadata(&wal_path).unwrap().len() > 0);
+
+            drop(handle);
+            assert_eq!(std::fs::metadata(&wal_path).unwrap().len(), 0);
+        });
+    }
+
+    #[test]
+    fn count_pending_by_account_type() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let db = Database::new(&db_path()).unwrap();
+
+            let res = db.count_pending_by_account_type().await.unwrap();
+            assert!(res.is_empty());
+
+            // Prepare addresses.
+            let alice = NetAccount::from("14GcE3qBiEnAyg2sDfadT3fQhWd2Z3M59tWi1CvVV8UwxUfU");
+            let bob = NetAccount::from("163AnENMFr6k4UWBGdHG9dTWgrDmnJgmh3HBBZuVWhUTTU5C");
+
+            let mut ident = OnChainIdentity::new(alice.clone()).unwrap();
+            ident
+                .push_account(AccountType::Matrix, Account::from("@alice:matrix.org"))
+                .unwrap();
+            ident
+                .push_account(AccountType::Email, Account::from("alice@example.com"))
+                .unwrap();
+            db.insert_identity(&ident).await.unwrap();
+
+            let mut ident = OnChainIdentity::new(bob.clone()).unwrap();
+            ident
+                .push_account(AccountType::Matrix, Account::from("@bob:matrix.org"))
+                .unwrap();
+            db.insert_identity(&ident).await.unwrap();
+
+            let res = db.count_pending_by_account_type().await.unwrap();
+            assert_eq!(res.len(), 2);
+            assert_eq!(res[&AccountType::Matrix], 2);
+            assert_eq!(res[&AccountType::Email], 1);
+
+            // Accepted challenges are no longer pending.
+            db.set_challenge_status(&alice, &AccountType::Email, &ChallengeStatus::Accepted)
+                .await
+                .unwrap();
+            db.set_challenge_status(&bob, &AccountType::Matrix, &ChallengeStatus::Accepted)
+                .await
+                .unwrap();
+
+            let res = db.count_pending_by_account_type().await.unwrap();
+            assert_eq!(res.len(), 1);
+            assert_eq!(res[&AccountType::Matrix], 1);
+        });
+    }
+
+    #[test]
+    fn query_plan_uses_indexes() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let db = Database::new(&db_path()).unwrap();
+            let con = db.con.lock().await;
+
+            let plan = |query: &str| -> String {
+                let mut stmt = con
+                    .prepare(&format!("EXPLAIN QUERY PLAN {}", query))
+                    .unwrap();
+                let mut rows = stmt.query(params![]).unwrap();
+
+                let mut plan = String::new();
+                while let Some(row) = rows.next().unwrap() {
+                    plan.push_str(&row.get::<_, String>(3).unwrap());
+                }
+
+                plan
+            };
+
+            assert!(
+                plan("SELECT id FROM pending_judgments WHERE net_account = 'alice'")
+                    .contains("USING COVERING INDEX")
+            );
+            assert!(
+                plan("SELECT id FROM account_states WHERE net_account_id = 1")
+                    .contains("USING COVERING INDEX")
+            );
+            assert!(
+                plan("SELECT challenge FROM account_states WHERE account = 'alice'")
+                    .contains("USING INDEX idx_account_states_account")
+            );
+        });
+    }
+
     #[test]
     fn insert_identity() {
         let mut rt = Runtime::new().unwrap();
@@ -1696,6 +2849,43 @@ mod tests {
         });
     }
 
+    #[test]
+    fn select_challenge() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let db = Database::new(&db_path()).unwrap();
+
+            // Create identity.
+            let alice = NetAccount::from("14GcE3qBiEnAyg2sDfadT3fQhWd2Z3M59tWi1CvVV8UwxUfU");
+            let mut alice_ident = OnChainIdentity::new(alice.clone()).unwrap();
+
+            alice_ident
+                .push_account(AccountType::Matrix, Account::from("@alice:matrix.org"))
+                .unwrap();
+
+            db.insert_identity(&alice_ident).await.unwrap();
+
+            let res = db
+                .select_challenge(&alice, &AccountType::Matrix)
+                .await
+                .unwrap();
+            assert_eq!(res.unwrap(), Challenge::gen_fixed());
+
+            let res = db
+                .select_challenge(&alice, &AccountType::Email)
+                .await
+                .unwrap();
+            assert!(res.is_none());
+
+            let bob = NetAccount::from("163AnENMFr6k4UWBGdHG9dTWgrDmnJgmh3HBBZuVWhUTTU5C");
+            let res = db
+                .select_challenge(&bob, &AccountType::Matrix)
+                .await
+                .unwrap();
+            assert!(res.is_none());
+        });
+    }
+
     #[test]
     fn select_delete_timed_out_identities() {
         let mut rt = Runtime::new().unwrap();
@@ -1742,20 +2932,74 @@ mod tests {
             assert!(res.contains(&alice));
             assert!(res.contains(&bob));
 
-            let res = db.select_identities().await.unwrap();
-            let accounts: Vec<&NetAccount> = res.iter().map(|ident| ident.net_account()).collect();
-            assert_eq!(accounts.len(), 3);
-            assert!(accounts.contains(&&alice));
-            assert!(accounts.contains(&&bob));
-            assert!(accounts.contains(&&eve));
+            let res = db.select_identities().await.unwrap();
+            let accounts: Vec<&NetAccount> = res.iter().map(|ident| ident.net_account()).collect();
+            assert_eq!(accounts.len(), 3);
+            assert!(accounts.contains(&&alice));
+            assert!(accounts.contains(&&bob));
+            assert!(accounts.contains(&&eve));
+
+            db.delete_identity(&bob).await.unwrap();
+
+            let res = db.select_identities().await.unwrap();
+            let accounts: Vec<&NetAccount> = res.iter().map(|ident| ident.net_account()).collect();
+            assert_eq!(accounts.len(), 2);
+            assert!(accounts.contains(&&alice));
+            assert!(accounts.contains(&&eve));
+        });
+    }
+
+    #[test]
+    fn select_pending_challenges() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let db = Database::new(&db_path()).unwrap();
+
+            let alice = NetAccount::from("14GcE3qBiEnAyg2sDfadT3fQhWd2Z3M59tWi1CvVV8UwxUfU");
+
+            let mut ident = OnChainIdentity::new(alice.clone()).unwrap();
+            ident
+                .push_account(AccountType::Matrix, Account::from("@alice:matrix.org"))
+                .unwrap();
+            ident
+                .push_account(AccountType::Email, Account::from("alice@example.com"))
+                .unwrap();
+            ident
+                .push_account(AccountType::Twitter, Account::from("@alice"))
+                .unwrap();
+            db.insert_identity(&ident).await.unwrap();
+
+            let res = db.select_pending_challenges().await.unwrap();
+            assert_eq!(res.len(), 3);
+            assert!(res.iter().all(|(net_account, _, created)| {
+                net_account == &alice && *created <= unix_time()
+            }));
+
+            // Accepted and invalid accounts are excluded.
+            db.set_challenge_status(&alice, &AccountType::Email, &ChallengeStatus::Accepted)
+                .await
+                .unwrap();
+            db.set_account_status(
+                &Account::from("@alice"),
+                &AccountType::Twitter,
+                &AccountStatus::Invalid,
+            )
+            .await
+            .unwrap();
+
+            let res = db.select_pending_challenges().await.unwrap();
+            assert_eq!(res.len(), 1);
+            assert_eq!(res[0].0, alice);
+            assert_eq!(res[0].1, AccountType::Matrix);
 
-            db.delete_identity(&bob).await.unwrap();
+            // Frozen identities are excluded.
+            db.freeze_identity(&alice).await.unwrap();
+            let res = db.select_pending_challenges().await.unwrap();
+            assert!(res.is_empty());
 
-            let res = db.select_identities().await.unwrap();
-            let accounts: Vec<&NetAccount> = res.iter().map(|ident| ident.net_account()).collect();
-            assert_eq!(accounts.len(), 2);
-            assert!(accounts.contains(&&alice));
-            assert!(accounts.contains(&&eve));
+            db.unfreeze_identity(&alice).await.unwrap();
+            let res = db.select_pending_challenges().await.unwrap();
+            assert_eq!(res.len(), 1);
         });
     }
 
@@ -1864,6 +3108,62 @@ mod tests {
         });
     }
 
+    #[test]
+    fn insert_identity_batch_twice() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let db = Database::new(&db_path()).unwrap();
+
+            let alice = NetAccount::alice();
+            let mut ident = OnChainIdentity::new(alice.clone()).unwrap();
+            ident
+                .push_account(AccountType::DisplayName, Account::from("Alice"))
+                .unwrap();
+            ident
+                .push_account(AccountType::Matrix, Account::from("@alice:matrix.org"))
+                .unwrap();
+
+            // The Watcher delivers the same judgement request again.
+            db.insert_identity_batch(&[&ident]).await.unwrap();
+            db.insert_identity_batch(&[&ident]).await.unwrap();
+
+            {
+                let con = db.con.lock().await;
+                let count: i64 = con
+                    .query_row(
+                        "SELECT COUNT(*) FROM pending_judgments WHERE net_account = ?1",
+                        params![alice],
+                        |row| row.get(0),
+                    )
+                    .unwrap();
+                assert_eq!(count, 1);
+            }
+
+            let res = db.select_identities().await.unwrap();
+            assert_eq!(res.len(), 1);
+            assert_eq!(res[0].net_account(), &alice);
+            assert_eq!(res[0].account_states().len(), 2);
+
+            let mut statuses = db.select_account_statuses(&alice).await.unwrap();
+            statuses.sort_by_key(|(account_ty, _, _)| account_ty.as_db_str());
+            assert_eq!(
+                statuses,
+                vec![
+                    (
+                        AccountType::DisplayName,
+                        Account::from("Alice"),
+                        AccountStatus::Unknown
+                    ),
+                    (
+                        AccountType::Matrix,
+                        Account::from("@alice:matrix.org"),
+                        AccountStatus::Unknown
+                    ),
+                ]
+            );
+        });
+    }
+
     #[test]
     fn insert_select_room_id() {
         let mut rt = Runtime::new().unwrap();
@@ -1917,6 +3217,80 @@ mod tests {
             assert_eq!(res.len(), 2);
             assert!(res.contains(&alice_room_2));
             assert!(res.contains(&bob_room));
+
+            // Reverse lookup.
+            let res = db
+                .select_net_account_by_room_id(&alice_room_2)
+                .await
+                .unwrap();
+            assert_eq!(res.unwrap(), alice);
+
+            let res = db.select_net_account_by_room_id(&bob_room).await.unwrap();
+            assert_eq!(res.unwrap(), bob);
+
+            // Replaced room.
+            let res = db
+                .select_net_account_by_room_id(&alice_room_1)
+                .await
+                .unwrap();
+            assert!(res.is_none());
+        });
+    }
+
+    #[test]
+    fn select_orphaned_room_ids_delete_room_id() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let db = Database::new(&db_path()).unwrap();
+
+            // Prepare addresses.
+            let alice = NetAccount::from("14GcE3qBiEnAyg2sDfadT3fQhWd2Z3M59tWi1CvVV8UwxUfU");
+            let bob = NetAccount::from("163AnENMFr6k4UWBGdHG9dTWgrDmnJgmh3HBBZuVWhUTTU5C");
+
+            // Insert identities.
+            let ident = OnChainIdentity::new(alice.clone()).unwrap();
+            db.insert_identity(&ident).await.unwrap();
+
+            let ident = OnChainIdentity::new(bob.clone()).unwrap();
+            db.insert_identity(&ident).await.unwrap();
+
+            let alice_room = RoomId::try_from("!ALICE:matrix.org").unwrap();
+            let bob_room = RoomId::try_from("!BOB:matrix.org").unwrap();
+
+            db.insert_room_id(&alice, &alice_room).await.unwrap();
+            db.insert_room_id(&bob, &bob_room).await.unwrap();
+
+            // No orphans yet.
+            let res = db.select_orphaned_room_ids().await.unwrap();
+            assert!(res.is_empty());
+
+            // Remove identity, the room is left behind.
+            db.remove_identity(&alice).await.unwrap();
+
+            let res = db.select_orphaned_room_ids().await.unwrap();
+            assert_eq!(res, vec![alice_room.clone()]);
+
+            let res = db.select_rooms().await.unwrap();
+            assert_eq!(res.len(), 2);
+            assert!(res.contains(&(alice_room.clone(), None)));
+            assert!(res.contains(&(bob_room.clone(), Some(bob.clone()))));
+
+            let res = db.select_net_account_by_room_id(&alice_room).await.unwrap();
+            assert!(res.is_none());
+
+            db.delete_room_id(&alice_room).await.unwrap();
+
+            let res = db.select_orphaned_room_ids().await.unwrap();
+            assert!(res.is_empty());
+
+            let res = db.select_room_ids().await.unwrap();
+            assert_eq!(res, vec![bob_room.clone()]);
+
+            // Repeated deletion has no effect.
+            db.delete_room_id(&alice_room).await.unwrap();
+
+            let res = db.select_room_ids().await.unwrap();
+            assert_eq!(res, vec![bob_room]);
         });
     }
 
@@ -2018,6 +3392,29 @@ mod tests {
                 .unwrap();
 
             assert!(intro_sent);
+
+            // Stored addresses which fail to parse are skipped.
+            let invalid = NetAccount::from("invalid");
+            db.con
+                .lock()
+                .await
+                .execute_named(
+                    "UPDATE pending_judgments SET net_account = :invalid WHERE net_account = :bob",
+                    named_params! {
+                        ":invalid": &invalid,
+                        ":bob": &bob,
+                    },
+                )
+                .unwrap();
+
+            let (res, _) = db
+                .select_challenge_data(&Account::from("@alice:matrix.org"), &AccountType::Matrix)
+                .await
+                .unwrap();
+            assert_eq!(res.len(), 1);
+            assert_eq!(res[0].0, NetworkAddress::try_from(alice).unwrap());
+
+            assert!(db.select_identity(&invalid).await.unwrap().is_none());
         });
     }
 
@@ -2290,6 +3687,39 @@ mod tests {
         });
     }
 
+    #[test]
+    fn insert_display_names() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let db = Database::new(&db_path()).unwrap();
+
+            let alice_net = NetAccount::alice();
+            let bob_net = NetAccount::bob();
+            let eve_net = NetAccount::eve();
+
+            let alice = Account::from("alice");
+            let bob = Account::from("bob");
+            let eve = Account::from("eve");
+
+            db.insert_display_names(&[(&alice, &alice_net), (&bob, &bob_net), (&eve, &eve_net)])
+                .await
+                .unwrap();
+
+            let res = db.select_display_names(&bob_net).await.unwrap();
+            assert_eq!(res.len(), 2);
+            assert!(res.contains(&alice));
+            assert!(res.contains(&eve));
+
+            // Repeated insert replaces the existing entries.
+            db.insert_display_names(&[(&alice, &alice_net), (&bob, &bob_net)])
+                .await
+                .unwrap();
+
+            let res = db.select_display_names(&eve_net).await.unwrap();
+            assert_eq!(res.len(), 2);
+        });
+    }
+
     #[test]
     fn insert_select_display_name_violations() {
         let mut rt = Runtime::new().unwrap();
@@ -2361,4 +3791,230 @@ mod tests {
             assert!(res.is_none());
         });
     }
+
+    #[test]
+    fn select_identity() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let db = Database::new(&db_path()).unwrap();
+
+            let alice = NetAccount::alice();
+            let bob = NetAccount::bob();
+
+            let mut ident = OnChainIdentity::new(alice.clone()).unwrap();
+            ident
+                .push_account(AccountType::Matrix, Account::from("@alice:matrix.org"))
+                .unwrap();
+            ident
+                .push_account(AccountType::Email, Account::from("alice@example.com"))
+                .unwrap();
+            db.insert_identity(&ident).await.unwrap();
+
+            let ident = OnChainIdentity::new(bob.clone()).unwrap();
+            db.insert_identity(&ident).await.unwrap();
+
+            db.set_account_status(
+                &Account::from("alice@example.com"),
+                &AccountType::Email,
+                &AccountStatus::Invalid,
+            )
+            .await
+            .unwrap();
+            db.set_challenge_status(&alice, &AccountType::Matrix, &ChallengeStatus::Accepted)
+                .await
+                .unwrap();
+
+            let res = db.select_identity(&alice).await.unwrap().unwrap();
+            assert_eq!(res.net_account(), &alice);
+            assert_eq!(res.account_states().len(), 2);
+
+            let state = res.get_account_state(&AccountType::Matrix).unwrap();
+            assert_eq!(state.account, Account::from("@alice:matrix.org"));
+            assert_eq!(state.account_status, AccountStatus::Unknown);
+            assert_eq!(state.challenge, Challenge::gen_fixed());
+            assert_eq!(state.challenge_status, ChallengeStatus::Accepted);
+
+            let state = res.get_account_state(&AccountType::Email).unwrap();
+            assert_eq!(state.account, Account::from("alice@example.com"));
+            assert_eq!(state.account_status, AccountStatus::Invalid);
+            assert_eq!(state.challenge_status, ChallengeStatus::Unconfirmed);
+
+            // Identity without any accounts.
+            let res = db.select_identity(&bob).await.unwrap().unwrap();
+            assert!(res.account_states().is_empty());
+
+            // Unknown identity.
+            let res = db.select_identity(&NetAccount::eve()).await.unwrap();
+            assert!(res.is_none());
+        });
+    }
+
+    #[test]
+    fn export_snapshot_csv() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let db = Database::new(&db_path()).unwrap();
+
+            let alice = NetAccount::alice();
+            let kusama = NetAccount::from("HNZata7iMYWmk5RvZRTiAsSDhV8366zq2YGb3tLH5Upf74F");
+
+            let mut ident = OnChainIdentity::new(alice.clone()).unwrap();
+            ident
+                .push_account(AccountType::Matrix, Account::from("@alice:matrix.org"))
+                .unwrap();
+            ident
+                .push_account(AccountType::DisplayName, Account::from("Alice"))
+                .unwrap();
+            ident
+                .push_account(AccountType::Email, Account::from("alice@example.com"))
+                .unwrap();
+            db.insert_identity(&ident).await.unwrap();
+
+            let mut ident = OnChainIdentity::new(kusama.clone()).unwrap();
+            ident
+                .push_account(AccountType::Twitter, Account::from("@ksm"))
+                .unwrap();
+            db.insert_identity(&ident).await.unwrap();
+
+            db.set_account_status(
+                &Account::from("alice@example.com"),
+                &AccountType::Email,
+                &AccountStatus::Invalid,
+            )
+            .await
+            .unwrap();
+            db.set_challenge_status(&alice, &AccountType::Matrix, &ChallengeStatus::Accepted)
+                .await
+                .unwrap();
+
+            let mut csv = vec![];
+            db.export_snapshot_csv(&mut csv).await.unwrap();
+            let csv = String::from_utf8(csv).unwrap();
+
+            let mut lines = csv.lines();
+            assert_eq!(
+                lines.next().unwrap(),
+                "network,address,field_type,field_address,challenge_status,validity,issued_at"
+            );
+
+            let expected = [
+                "polkadot,14GcE3qBiEnAyg2sDfadT3fQhWd2Z3M59tWi1CvVV8UwxUfU,display_name,Alice,unconfirmed,unknown",
+                "polkadot,14GcE3qBiEnAyg2sDfadT3fQhWd2Z3M59tWi1CvVV8UwxUfU,email,alice@example.com,unconfirmed,invalid",
+                "polkadot,14GcE3qBiEnAyg2sDfadT3fQhWd2Z3M59tWi1CvVV8UwxUfU,matrix,'@alice:matrix.org,accepted,unknown",
+                "kusama,HNZata7iMYWmk5RvZRTiAsSDhV8366zq2YGb3tLH5Upf74F,twitter,'@ksm,unconfirmed,unknown",
+            ];
+
+            for expected in &expected {
+                let line = lines.next().unwrap();
+                let (record, issued_at) = line.split_at(line.rfind(',').unwrap());
+
+                assert_eq!(&record, expected);
+                assert!(issued_at[1..].parse::<u64>().unwrap() <= unix_time());
+            }
+
+            assert!(lines.next().is_none());
+        });
+    }
+
+    #[test]
+    fn escape_csv_formulas() {
+        assert_eq!(
+            escape_csv_formula("=HYPERLINK(\"https://evil.com\")"),
+            "'=HYPERLINK(\"https://evil.com\")"
+        );
+        assert_eq!(escape_csv_formula("+1"), "'+1");
+        assert_eq!(escape_csv_formula("-1"), "'-1");
+        assert_eq!(escape_csv_formula("@SUM(A1)"), "'@SUM(A1)");
+        assert_eq!(escape_csv_formula("\t=1"), "'\t=1");
+        assert_eq!(escape_csv_formula("alice@example.com"), "alice@example.com");
+        assert_eq!(escape_csv_formula(""), "");
+    }
+
+    #[test]
+    fn select_pending_by_age() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let db = Database::new(&db_path()).unwrap();
+
+            let alice = NetAccount::alice();
+            let bob = NetAccount::bob();
+            let eve = NetAccount::eve();
+
+            for net_account in &[&alice, &bob, &eve] {
+                let ident = OnChainIdentity::new((*net_account).clone()).unwrap();
+                db.insert_identity(&ident).await.unwrap();
+            }
+
+            // Backdate the judgement requests.
+            let now = unix_time();
+            {
+                let con = db.con.lock().await;
+                for (net_account, age) in &[(&alice, 60), (&bob, 3600), (&eve, 600)] {
+                    con.execute_named(
+                        "UPDATE pending_judgments
+                            SET created = :created
+                            WHERE net_account = :net_account",
+                        named_params! {
+                            ":created": (now - age) as i64,
+                            ":net_account": net_account,
+                        },
+                    )
+                    .unwrap();
+                }
+            }
+
+            let res = db.select_pending_by_age().await.unwrap();
+            assert_eq!(
+                res.iter()
+                    .map(|(net_account, _)| net_account.clone())
+                    .collect::<Vec<NetAccount>>(),
+                vec![bob.clone(), eve.clone(), alice.clone()]
+            );
+
+            // Allow for the clock to advance while running the test.
+            let ages = res.into_iter().map(|(_, age)| age).collect::<Vec<u64>>();
+            assert!(ages[0] >= 3600 && ages[0] < 3610);
+            assert!(ages[1] >= 600 && ages[1] < 610);
+            assert!(ages[2] >= 60 && ages[2] < 70);
+
+            let res = db.select_pending_since(&bob).await.unwrap();
+            assert_eq!(res, Some(now - 3600));
+
+            db.delete_identity(&bob).await.unwrap();
+
+            let res = db.select_pending_since(&bob).await.unwrap();
+            assert!(res.is_none());
+
+            let res = db.select_pending_by_age().await.unwrap();
+            assert_eq!(res.len(), 2);
+        });
+    }
+
+    #[test]
+    fn freeze_identity() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let db = Database::new(&db_path()).unwrap();
+
+            let alice = NetAccount::alice();
+            let bob = NetAccount::bob();
+
+            assert!(!db.is_frozen(&alice).await.unwrap());
+
+            // Freezing twice is fine.
+            db.freeze_identity(&alice).await.unwrap();
+            db.freeze_identity(&alice).await.unwrap();
+            assert!(db.is_frozen(&alice).await.unwrap());
+            assert!(!db.is_frozen(&bob).await.unwrap());
+
+            // The freeze survives the removal of the identity.
+            let ident = OnChainIdentity::new(alice.clone()).unwrap();
+            db.insert_identity(&ident).await.unwrap();
+            db.remove_identity(&alice).await.unwrap();
+            assert!(db.is_frozen(&alice).await.unwrap());
+
+            db.unfreeze_identity(&alice).await.unwrap();
+            assert!(!db.is_frozen(&alice).await.unwrap());
+        });
+    }
 }
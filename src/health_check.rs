@@ -1,26 +1,613 @@
-use crate::primitives::Result;
-use actix_web::http::StatusCode;
-use actix_web::{get, rt, App, HttpServer, Responder};
+use crate::connector::{Connectivity, ConnectivityState};
+use crate::middleware::RequestLogger;
+use crate::primitives::{AccountType, NetAccount, Result};
+use crate::Database;
+use actix_web::middleware::Compress;
+use actix_web::{
+    get, http::header, post, rt, web, App, HttpRequest, HttpResponse, HttpServer, Responder,
+};
+use futures::future;
+use std::collections::HashMap;
 
-/// Currently, the health check endpoint just returns a "200 OK" response. In
-/// the future, this service might be improved for more advanced reporting.
+/// The admin endpoints are bound to localhost on a separate port, so they are
+/// never exposed by the Service in front of the health check.
+const ADMIN_ADDRESS: &'static str = "127.0.0.1:8081";
+
+/// The amount of judgements listed by default, and at most, by the
+/// `recent_judgements` endpoint.
+const DEFAULT_JUDGEMENTS_LIMIT: u32 = 50;
+const MAX_JUDGEMENTS_LIMIT: u32 = 1000;
+
+/// The health check endpoint reports whether the database is accessible and
+/// whether the Watcher is connected, including the state of the connector.
+///
+/// The endpoint serves as the liveness probe, so it only responds with "503
+/// Service Unavailable" if the database is inaccessible. Restarting the
+/// process does not help with an unreachable Watcher, hence its state is only
+/// reported in the body.
 pub struct HealthCheck {}
 
+struct HealthCheckState {
+    db: Database,
+    // `None` if the Watcher connector is disabled.
+    connectivity: Option<Connectivity>,
+    // Bearer token required by the admin endpoints which change state.
+    admin_token: String,
+}
+
+/// Which admin endpoints to serve on the admin port.
+pub struct AdminOptions {
+    /// Read-only export of the pending identities.
+    pub enable_export: bool,
+    /// Freezing and unfreezing of identities, requires `token`.
+    pub enable_freeze: bool,
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+struct HealthReport {
+    status: String,
+    db: String,
+    watcher: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    connectivity: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    connected_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pending_by_type: Option<HashMap<AccountType, u64>>,
+}
+
+impl HealthReport {
+    /// The database check consists of counting the pending accounts, which
+    /// are included in the report.
+    fn new(
+        pending_by_type: Result<HashMap<AccountType, u64>>,
+        connectivity: Option<ConnectivityState>,
+    ) -> Self {
+        let mut healthy = true;
+
+        let (db, pending_by_type) = match pending_by_type {
+            Ok(counts) => ("ok".to_string(), Some(counts)),
+            Err(err) => {
+                healthy = false;
+                (format!("error: {}", err), None)
+            }
+        };
+
+        let (watcher, connected_secs) = match &connectivity {
+            Some(ConnectivityState::Connected { since }) => {
+                ("connected", Some(since.elapsed().as_secs()))
+            }
+            Some(_) => {
+                healthy = false;
+                ("disconnected", None)
+            }
+            None => ("disabled", None),
+        };
+
+        HealthReport {
+            status: if healthy { "ok" } else { "degraded" }.to_string(),
+            db: db,
+            watcher: watcher.to_string(),
+            connectivity: connectivity.map(|state| state.to_string()),
+            connected_secs: connected_secs,
+            pending_by_type: pending_by_type,
+        }
+    }
+    fn is_healthy(&self) -> bool {
+        self.status == "ok"
+    }
+    fn is_alive(&self) -> bool {
+        self.pending_by_type.is_some()
+    }
+}
+
 #[get("/healthcheck")]
-async fn endpoint() -> impl Responder {
-    "OK".with_status(StatusCode::OK)
+async fn endpoint(state: web::Data<HealthCheckState>) -> impl Responder {
+    let report = HealthReport::new(
+        state.db.count_pending_by_account_type().await,
+        state
+            .connectivity
+            .as_ref()
+            .map(|connectivity| connectivity.state()),
+    );
+
+    if report.is_alive() {
+        HttpResponse::Ok().json(report)
+    } else {
+        HttpResponse::ServiceUnavailable().json(report)
+    }
+}
+
+/// Exports all pending identities as CSV. Only served if enabled in the
+/// config, since it exposes the contact information of users.
+#[get("/api/v1/admin/export.csv")]
+async fn export_csv(state: web::Data<HealthCheckState>) -> impl Responder {
+    let mut csv = vec![];
+
+    match state.db.export_snapshot_csv(&mut csv).await {
+        Ok(()) => HttpResponse::Ok()
+            .content_type("text/csv")
+            .header(
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"export.csv\"",
+            )
+            .body(csv),
+        Err(err) => {
+            error!("Failed to export pending identities: {}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+struct PendingIdentity {
+    address: NetAccount,
+    pending_secs: u64,
+}
+
+/// Lists all pending identities, longest pending first. Only served if
+/// enabled in the config.
+#[get("/api/v1/admin/pending")]
+async fn pending_identities(state: web::Data<HealthCheckState>) -> impl Responder {
+    match state.db.select_pending_by_age().await {
+        Ok(pending) => HttpResponse::Ok().json(
+            pending
+                .into_iter()
+                .map(|(address, pending_secs)| PendingIdentity {
+                    address: address,
+                    pending_secs: pending_secs,
+                })
+                .collect::<Vec<PendingIdentity>>(),
+        ),
+        Err(err) => {
+            error!("Failed to fetch pending identities: {}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RecentJudgementsQuery {
+    limit: Option<u32>,
+    // Unix timestamp.
+    since: Option<u64>,
+}
+
+/// Lists the most recently issued judgements, newest first. Only served if
+/// enabled in the config.
+#[get("/api/v1/admin/recent_judgements")]
+async fn recent_judgements(
+    state: web::Data<HealthCheckState>,
+    query: web::Query<RecentJudgementsQuery>,
+) -> impl Responder {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_JUDGEMENTS_LIMIT)
+        .min(MAX_JUDGEMENTS_LIMIT);
+
+    match state.db.select_recent_judgements(limit, query.since).await {
+        Ok(judgements) => HttpResponse::Ok().json(judgements),
+        Err(err) => {
+            error!("Failed to fetch recent judgements: {}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Checks the `Authorization: Bearer <token>` header of the request. An empty
+/// token never authorizes anything.
+fn is_authorized(req: &HttpRequest, token: &str) -> bool {
+    if token.is_empty() {
+        return false;
+    }
+
+    let provided = match req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(value) if value.starts_with("Bearer ") => &value["Bearer ".len()..],
+        _ => return false,
+    };
+
+    // Compare in constant time.
+    provided.len() == token.len()
+        && provided
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// Halts the verification of the given address. Only served if enabled in
+/// the config.
+#[post("/api/v1/admin/freeze/{address}")]
+async fn freeze_identity(
+    req: HttpRequest,
+    state: web::Data<HealthCheckState>,
+    address: web::Path<String>,
+) -> impl Responder {
+    if !is_authorized(&req, &state.admin_token) {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    let net_account = NetAccount::from(address.into_inner());
+    match state.db.freeze_identity(&net_account).await {
+        Ok(()) => {
+            warn!("Froze identity: {}", net_account);
+            HttpResponse::Ok().finish()
+        }
+        Err(err) => {
+            error!("Failed to freeze identity: {}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Resumes the verification of the given address. Only served if enabled in
+/// the config.
+#[post("/api/v1/admin/unfreeze/{address}")]
+async fn unfreeze_identity(
+    req: HttpRequest,
+    state: web::Data<HealthCheckState>,
+    address: web::Path<String>,
+) -> impl Responder {
+    if !is_authorized(&req, &state.admin_token) {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    let net_account = NetAccount::from(address.into_inner());
+    match state.db.unfreeze_identity(&net_account).await {
+        Ok(()) => {
+            info!("Unfroze identity: {}", net_account);
+            HttpResponse::Ok().finish()
+        }
+        Err(err) => {
+            error!("Failed to unfreeze identity: {}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
 }
 
 impl HealthCheck {
-    pub fn start() -> Result<()> {
+    pub fn start(
+        db: Database,
+        connectivity: Option<Connectivity>,
+        admin: AdminOptions,
+    ) -> Result<()> {
         let mut sys = rt::System::new("health check service");
 
-        let server = HttpServer::new(|| App::new().service(endpoint))
-            .bind("0.0.0.0:8080")?
-            .run();
+        let state = web::Data::new(HealthCheckState {
+            db: db,
+            connectivity: connectivity,
+            admin_token: admin.token,
+        });
 
-        sys.block_on(server)?;
+        let health_state = state.clone();
+        let server = HttpServer::new(move || {
+            // Compresses responses if requested by the client via
+            // `Accept-Encoding`.
+            App::new()
+                .wrap(Compress::default())
+                .wrap(RequestLogger)
+                .app_data(health_state.clone())
+                .service(endpoint)
+        })
+        .bind("0.0.0.0:8080")?
+        .run();
+
+        if !admin.enable_export && !admin.enable_freeze {
+            sys.block_on(server)?;
+            return Ok(());
+        }
+
+        let (enable_export, enable_freeze) = (admin.enable_export, admin.enable_freeze);
+        let admin_server = HttpServer::new(move || {
+            let app = App::new()
+                .wrap(Compress::default())
+                .wrap(RequestLogger)
+                .app_data(state.clone());
+
+            let app = if enable_export {
+                app.service(export_csv)
+                    .service(pending_identities)
+                    .service(recent_judgements)
+            } else {
+                app
+            };
+
+            if enable_freeze {
+                app.service(freeze_identity).service(unfreeze_identity)
+            } else {
+                app
+            }
+        })
+        .bind(ADMIN_ADDRESS)?
+        .run();
+
+        sys.block_on(future::try_join(server, admin_server))?;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connector::ConnectivityEvent;
+    use crate::manager::OnChainIdentity;
+    use crate::primitives::{Account, Challenge, Judgement};
+    use actix_web::test;
+    use std::time::Instant;
+
+    #[test]
+    fn health_report() {
+        let counts: HashMap<AccountType, u64> =
+            [(AccountType::Matrix, 2)].iter().cloned().collect();
+
+        let report = HealthReport::new(Ok(counts), None);
+        assert!(report.is_healthy());
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            serde_json::json!({
+                "status": "ok",
+                "db": "ok",
+                "watcher": "disabled",
+                "pending_by_type": {
+                    "matrix": 2,
+                },
+            })
+        );
+
+        let report = HealthReport::new(Err(failure::err_msg("locked")), None);
+        assert!(!report.is_healthy());
+        assert!(!report.is_alive());
+        assert_eq!(report.db, "error: locked");
+        assert!(report.pending_by_type.is_none());
+    }
+
+    #[test]
+    fn health_report_connectivity() {
+        let report = HealthReport::new(
+            Ok(HashMap::new()),
+            Some(ConnectivityState::Connected {
+                since: Instant::now(),
+            }),
+        );
+        assert!(report.is_healthy());
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            serde_json::json!({
+                "status": "ok",
+                "db": "ok",
+                "watcher": "connected",
+                "connectivity": "connected",
+                "connected_secs": 0,
+                "pending_by_type": {},
+            })
+        );
+
+        let report = HealthReport::new(
+            Ok(HashMap::new()),
+            Some(ConnectivityState::Reconnecting {
+                attempt: 2,
+                last_error: "refused".to_string(),
+            }),
+        );
+        assert!(!report.is_healthy());
+        assert!(report.is_alive());
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            serde_json::json!({
+                "status": "degraded",
+                "db": "ok",
+                "watcher": "disconnected",
+                "connectivity": "reconnecting (attempt 2, last error: refused)",
+                "pending_by_type": {},
+            })
+        );
+
+        // Never connected.
+        let report = HealthReport::new(
+            Ok(HashMap::new()),
+            Some(ConnectivityState::Connecting { attempt: 1 }),
+        );
+        assert!(!report.is_healthy());
+
+        let report = HealthReport::new(Ok(HashMap::new()), Some(ConnectivityState::Failed));
+        assert!(!report.is_healthy());
+        assert!(report.is_alive());
+    }
+
+    #[test]
+    fn liveness_ignores_watcher() {
+        let mut sys = rt::System::new("health check liveness test");
+        sys.block_on(async {
+            let db = Database::new(&format!("/tmp/sqlite_{}", Challenge::gen_random().as_str()))
+                .unwrap();
+
+            let connectivity = Connectivity::default();
+            connectivity.transition(ConnectivityEvent::ConnectionFailed("refused".to_string()));
+
+            let state = web::Data::new(HealthCheckState {
+                db: db,
+                connectivity: Some(connectivity),
+                admin_token: String::new(),
+            });
+
+            let mut app = test::init_service(App::new().app_data(state).service(endpoint)).await;
+
+            let req = test::TestRequest::get().uri("/healthcheck").to_request();
+            let res = test::call_service(&mut app, req).await;
+            assert!(res.status().is_success());
+
+            let report: serde_json::Value = test::read_body_json(res).await;
+            assert_eq!(report["status"], "degraded");
+            assert_eq!(report["watcher"], "disconnected");
+        });
+    }
+
+    #[test]
+    fn compressed_responses() {
+        let mut sys = rt::System::new("health check compression test");
+        sys.block_on(async {
+            let db = Database::new(&format!("/tmp/sqlite_{}", Challenge::gen_random().as_str()))
+                .unwrap();
+
+            for net_account in &[NetAccount::alice(), NetAccount::bob(), NetAccount::eve()] {
+                let mut ident = OnChainIdentity::new(net_account.clone()).unwrap();
+                ident
+                    .push_account(AccountType::Matrix, Account::from("@user:matrix.org"))
+                    .unwrap();
+                ident
+                    .push_account(AccountType::Email, Account::from("user@example.com"))
+                    .unwrap();
+                ident
+                    .push_account(AccountType::Twitter, Account::from("@user"))
+                    .unwrap();
+                db.insert_identity(&ident).await.unwrap();
+            }
+
+            let state = web::Data::new(HealthCheckState {
+                db: db,
+                connectivity: None,
+                admin_token: String::new(),
+            });
+
+            let mut app = test::init_service(
+                App::new()
+                    .wrap(Compress::default())
+                    .app_data(state)
+                    .service(export_csv),
+            )
+            .await;
+
+            let req = test::TestRequest::get()
+                .uri("/api/v1/admin/export.csv")
+                .to_request();
+            let res = test::call_service(&mut app, req).await;
+            assert!(res.status().is_success());
+            assert!(!res.headers().contains_key(header::CONTENT_ENCODING));
+            let plain = test::read_body(res).await;
+
+            let req = test::TestRequest::get()
+                .uri("/api/v1/admin/export.csv")
+                .header(header::ACCEPT_ENCODING, "gzip")
+                .to_request();
+            let res = test::call_service(&mut app, req).await;
+            assert!(res.status().is_success());
+            assert_eq!(res.headers().get(header::CONTENT_ENCODING).unwrap(), "gzip");
+            let compressed = test::read_body(res).await;
+
+            assert!(compressed.len() < plain.len());
+        });
+    }
+
+    #[test]
+    fn recent_judgements_endpoint() {
+        let mut sys = rt::System::new("admin recent judgements test");
+        sys.block_on(async {
+            let db = Database::new(&format!("/tmp/sqlite_{}", Challenge::gen_random().as_str()))
+                .unwrap();
+
+            for net_account in &[NetAccount::alice(), NetAccount::bob()] {
+                let mut ident = OnChainIdentity::new(net_account.clone()).unwrap();
+                ident
+                    .push_account(AccountType::Matrix, Account::from("@user:matrix.org"))
+                    .unwrap();
+                db.insert_identity(&ident).await.unwrap();
+                db.insert_submitted_judgement(net_account, &Judgement::Reasonable)
+                    .await
+                    .unwrap();
+                db.insert_judgement_log(net_account).await.unwrap();
+            }
+
+            let state = web::Data::new(HealthCheckState {
+                db: db,
+                connectivity: None,
+                admin_token: String::new(),
+            });
+
+            let mut app =
+                test::init_service(App::new().app_data(state).service(recent_judgements)).await;
+
+            let req = test::TestRequest::get()
+                .uri("/api/v1/admin/recent_judgements")
+                .to_request();
+            let res = test::call_service(&mut app, req).await;
+            assert!(res.status().is_success());
+            let judgements: serde_json::Value = test::read_body_json(res).await;
+            assert_eq!(judgements.as_array().unwrap().len(), 2);
+
+            let req = test::TestRequest::get()
+                .uri("/api/v1/admin/recent_judgements?limit=1")
+                .to_request();
+            let res = test::call_service(&mut app, req).await;
+            let judgements: serde_json::Value = test::read_body_json(res).await;
+            assert_eq!(
+                judgements,
+                serde_json::json!([{
+                    "net_account": NetAccount::bob().as_str(),
+                    "judgement": "reasonable",
+                    "judged_at": judgements[0]["judged_at"],
+                    "field_count": 1,
+                }])
+            );
+        });
+    }
+
+    #[test]
+    fn freeze_requires_token() {
+        let mut sys = rt::System::new("admin freeze test");
+        sys.block_on(async {
+            let db = Database::new(&format!("/tmp/sqlite_{}", Challenge::gen_random().as_str()))
+                .unwrap();
+
+            let state = web::Data::new(HealthCheckState {
+                db: db.clone(),
+                connectivity: None,
+                admin_token: "secret".to_string(),
+            });
+
+            let mut app = test::init_service(
+                App::new()
+                    .app_data(state)
+                    .service(freeze_identity)
+                    .service(unfreeze_identity),
+            )
+            .await;
+
+            let alice = NetAccount::alice();
+            let uri = format!("/api/v1/admin/freeze/{}", alice);
+
+            // Missing or wrong token.
+            for auth in &[None, Some("Bearer wrong"), Some("secret")] {
+                let mut req = test::TestRequest::post().uri(&uri);
+                if let Some(auth) = auth {
+                    req = req.header(header::AUTHORIZATION, *auth);
+                }
+
+                let res = test::call_service(&mut app, req.to_request()).await;
+                assert_eq!(res.status().as_u16(), 401);
+            }
+            assert!(!db.is_frozen(&alice).await.unwrap());
+
+            let req = test::TestRequest::post()
+                .uri(&uri)
+                .header(header::AUTHORIZATION, "Bearer secret")
+                .to_request();
+            let res = test::call_service(&mut app, req).await;
+            assert!(res.status().is_success());
+            assert!(db.is_frozen(&alice).await.unwrap());
+
+            let req = test::TestRequest::post()
+                .uri(&format!("/api/v1/admin/unfreeze/{}", alice))
+                .header(header::AUTHORIZATION, "Bearer secret")
+                .to_request();
+            let res = test::call_service(&mut app, req).await;
+            assert!(res.status().is_success());
+            assert!(!db.is_frozen(&alice).await.unwrap());
+        });
+    }
+}
@@ -7,30 +7,41 @@ extern crate serde;
 #[macro_use]
 extern crate failure;
 
+pub use adapters::{
+    AdapterError, MatrixClient, ReminderScheduler, SmtpImapClientBuilder, TwitterBuilder,
+    WebhookNotifier, DEFAULT_ADAPTER_TIMEOUT,
+};
 use adapters::{
     DisplayNameHandler, EmailHandler, EmailTransport, MatrixHandler, MatrixTransport,
     TwitterHandler, TwitterTransport,
 };
-pub use adapters::{MatrixClient, SmtpImapClientBuilder, TwitterBuilder};
-use comms::{CommsMain, CommsVerifier};
-use connector::{Connector, ConnectorInitTransports};
+use comms::{CommsMain, CommsVerifier, Shutdown};
 pub use connector::{
-    ConnectorReaderTransport, ConnectorWriterTransport, WebSocketReader, WebSocketWriter,
-    WebSockets,
+    Connectivity, ConnectivityState, ConnectorReaderTransport, ConnectorWriterTransport,
+    WebSocketReader, WebSocketWriter, WebSockets,
 };
+use connector::{ConnectivityEvent, Connector, ConnectorInitTransports};
+#[cfg(any(test, feature = "test-utils"))]
+pub use connector::{EventType, Message};
 pub use db::Database;
-pub use health_check::HealthCheck;
+use futures::future;
+pub use health_check::{AdminOptions, HealthCheck};
 use manager::{IdentityManager, IdentityManagerConfig};
 pub use primitives::Account;
 use primitives::{AccountType, Fatal, Result};
 use std::env;
-use std::fs::File;
+use std::fmt;
+use std::fs::{self, File};
+use std::future::Future;
 use std::io::prelude::*;
+use std::path::Path;
 use std::process::exit;
-#[cfg(test)]
+use std::result::Result as StdResult;
 use std::sync::Arc;
 #[cfg(test)]
 use tests::mocks::{ConnectorMocker, ConnectorReaderMocker, EventManager};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::task::JoinHandle;
 use tokio::time::{self, Duration};
 
 pub mod adapters;
@@ -39,7 +50,10 @@ mod connector;
 mod db;
 mod health_check;
 mod manager;
+mod middleware;
 mod primitives;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod testing;
 #[cfg(test)]
 mod tests;
 mod verifier;
@@ -53,10 +67,24 @@ pub struct Config {
     pub enable_watcher: bool,
     pub enable_accounts: bool,
     pub enable_health_check: bool,
+    // Serves the read-only admin endpoints (`/api/v1/admin/export.csv`,
+    // `/api/v1/admin/pending` and `/api/v1/admin/recent_judgements`) on the
+    // admin port, bound to localhost.
+    #[serde(default)]
+    pub enable_admin_export: bool,
+    // Serves the endpoints to freeze and unfreeze identities
+    // (`/api/v1/admin/(un)freeze/{address}`) on the admin port. Requests
+    // must provide `admin_token` as bearer token.
+    #[serde(default)]
+    pub enable_admin_freeze: bool,
+    #[serde(default)]
+    pub admin_token: String,
     //
     pub matrix_homeserver: String,
     pub matrix_username: String,
     pub matrix_password: String,
+    #[serde(default = "default_matrix_max_messages_per_second")]
+    pub matrix_max_messages_per_second: f32,
     //
     pub twitter_screen_name: String,
     pub twitter_api_key: String,
@@ -69,6 +97,220 @@ pub struct Config {
     pub email_inbox: String,
     pub email_user: String,
     pub email_password: String,
+    //
+    pub webhook_url: Option<String>,
+    pub webhook_secret: Option<String>,
+    //
+    #[serde(default = "default_reminder_delay_hours")]
+    pub reminder_delay_hours: u64,
+    #[serde(default = "default_max_reminders")]
+    pub max_reminders: u32,
+    //
+    #[serde(default = "default_db_vacuum_interval_hours")]
+    pub db_vacuum_interval_hours: u64,
+    pub comms_channel_capacity: Option<usize>,
+    #[serde(default = "default_sqlite_busy_timeout_ms")]
+    pub sqlite_busy_timeout_ms: u64,
+    // Timeout (in seconds) of calls to the Matrix homeserver (except for the
+    // initial sync), the Twitter API and the SMTP/IMAP servers. Defaults to
+    // `DEFAULT_ADAPTER_TIMEOUT`.
+    pub adapter_timeout_seconds: Option<u64>,
+}
+
+fn default_matrix_max_messages_per_second() -> f32 {
+    1.0
+}
+
+fn default_reminder_delay_hours() -> u64 {
+    24
+}
+
+fn default_max_reminders() -> u32 {
+    3
+}
+
+fn default_db_vacuum_interval_hours() -> u64 {
+    24
+}
+
+fn default_sqlite_busy_timeout_ms() -> u64 {
+    5000
+}
+
+#[derive(Debug, Fail, Eq, PartialEq)]
+pub enum ConfigError {
+    #[fail(display = "'{}' must not be empty", 0)]
+    EmptyField(&'static str),
+    #[fail(display = "'{}' must be a {} URL, got: {}", 0, 1, 2)]
+    InvalidUrl(&'static str, &'static str, String),
+    #[fail(display = "'{}' is not writable: {}", 0, 1)]
+    NotWritable(&'static str, String),
+    #[fail(display = "'{}' must be greater than zero", 0)]
+    Zero(&'static str),
+    #[fail(
+        display = "'{}' must be a finite number greater than zero, got: {}",
+        0, 1
+    )]
+    NotPositive(&'static str, String),
+}
+
+/// All problems found by `Config::validate`.
+#[derive(Debug, Fail, Eq, PartialEq)]
+pub struct InvalidConfig(pub Vec<ConfigError>);
+
+impl fmt::Display for InvalidConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid config: ")?;
+        for (i, err) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", err)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    /// Checks the configuration for common mistakes, such as empty fields or
+    /// malformed URLs. Returns all problems at once, so they can be fixed in
+    /// one go.
+    pub fn validate(&self) -> StdResult<(), Vec<ConfigError>> {
+        let mut errors = vec![];
+
+        let mut required = vec![
+            ("registrar_db_path", &self.registrar_db_path),
+            ("matrix_db_path", &self.matrix_db_path),
+        ];
+
+        if self.enable_watcher {
+            required.push(("watcher_url", &self.watcher_url));
+        }
+
+        if self.enable_admin_freeze {
+            required.push(("admin_token", &self.admin_token));
+        }
+
+        if self.enable_accounts {
+            required.extend_from_slice(&[
+                ("matrix_homeserver", &self.matrix_homeserver),
+                ("matrix_username", &self.matrix_username),
+                ("matrix_password", &self.matrix_password),
+                ("twitter_screen_name", &self.twitter_screen_name),
+                ("twitter_api_key", &self.twitter_api_key),
+                ("twitter_api_secret", &self.twitter_api_secret),
+                ("twitter_token", &self.twitter_token),
+                ("twitter_token_secret", &self.twitter_token_secret),
+                ("email_server", &self.email_server),
+                ("imap_server", &self.imap_server),
+                ("email_inbox", &self.email_inbox),
+                ("email_user", &self.email_user),
+                ("email_password", &self.email_password),
+            ]);
+        }
+
+        for (name, value) in required {
+            if value.trim().is_empty() {
+                errors.push(ConfigError::EmptyField(name));
+            }
+        }
+
+        if self.enable_watcher {
+            check_url(
+                &mut errors,
+                "watcher_url",
+                &self.watcher_url,
+                &["ws", "wss"],
+                "ws:// or wss://",
+            );
+        }
+
+        if self.enable_accounts {
+            check_url(
+                &mut errors,
+                "matrix_homeserver",
+                &self.matrix_homeserver,
+                &["https"],
+                "https://",
+            );
+        }
+
+        if !self.registrar_db_path.is_empty() {
+            if let Err(err) = check_writable(&self.registrar_db_path) {
+                errors.push(ConfigError::NotWritable("registrar_db_path", err));
+            }
+        }
+
+        // The rate limiter cannot be constructed otherwise.
+        let rate = self.matrix_max_messages_per_second;
+        if !(rate.is_finite() && rate > 0.0) {
+            errors.push(ConfigError::NotPositive(
+                "matrix_max_messages_per_second",
+                rate.to_string(),
+            ));
+        }
+
+        if self.comms_channel_capacity == Some(0) {
+            errors.push(ConfigError::Zero("comms_channel_capacity"));
+        }
+
+        if self.adapter_timeout_seconds == Some(0) {
+            errors.push(ConfigError::Zero("adapter_timeout_seconds"));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+fn check_url(
+    errors: &mut Vec<ConfigError>,
+    name: &'static str,
+    value: &str,
+    schemes: &[&str],
+    expected: &'static str,
+) {
+    // Empty fields are reported separately.
+    if value.is_empty() {
+        return;
+    }
+
+    match url::Url::parse(value) {
+        Ok(url) if schemes.contains(&url.scheme()) => {}
+        _ => errors.push(ConfigError::InvalidUrl(name, expected, value.to_string())),
+    }
+}
+
+/// Checks whether the database at the given path can be written to, without
+/// touching the file itself. SQLite creates the journal and the write-ahead
+/// log next to the database, so the parent directory must be writable too.
+fn check_writable(path: &str) -> StdResult<(), String> {
+    let path = Path::new(path);
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    let metadata = fs::metadata(parent).map_err(|err| format!("{}: {}", parent.display(), err))?;
+    if !metadata.is_dir() {
+        return Err(format!("{}: not a directory", parent.display()));
+    }
+    if metadata.permissions().readonly() {
+        return Err(format!("{}: directory is read-only", parent.display()));
+    }
+
+    // The database does not have to exist yet.
+    if let Ok(metadata) = fs::metadata(path) {
+        if metadata.permissions().readonly() {
+            return Err(format!("{}: file is read-only", path.display()));
+        }
+    }
+
+    Ok(())
 }
 
 fn open_config() -> Result<Config> {
@@ -94,6 +336,8 @@ fn open_config() -> Result<Config> {
     Ok(config)
 }
 
+/// Opens the config and initializes the logger. Returns `InvalidConfig` if
+/// the config does not pass `Config::validate`.
 pub fn init_env() -> Result<Config> {
     let config = open_config()?;
 
@@ -110,14 +354,67 @@ pub fn init_env() -> Result<Config> {
 
     println!("Logger initiated");
 
+    config.validate().map_err(InvalidConfig)?;
+
     Ok(config)
 }
 
-pub async fn block() {
-    let mut interval = time::interval(Duration::from_secs(60));
-    loop {
-        interval.tick().await;
+/// How long the tasks get to finish their current work on shutdown.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The background tasks of the bot, which are stopped together on shutdown.
+#[derive(Default)]
+pub struct Tasks {
+    shutdown: Shutdown,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl Tasks {
+    fn spawn<F: 'static + Send + Future<Output = ()>>(&mut self, task: F) {
+        self.handles.push(tokio::spawn(task));
+    }
+    fn shutdown(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+    /// Signals all tasks to stop and waits until they finished. Returns
+    /// `false` if not all tasks finished within the timeout.
+    async fn stop(self, timeout: Duration) -> bool {
+        self.shutdown.trigger();
+        time::timeout(timeout, future::join_all(self.handles))
+            .await
+            .is_ok()
+    }
+}
+
+/// Blocks until the process receives either SIGTERM or SIGINT. Then stops all
+/// tasks, waiting for at most 30 seconds, and writes the write-ahead log back
+/// into the database.
+pub async fn block(tasks: Tasks, db: Database) -> Result<()> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+
+    tokio::select! {
+        _ = sigterm.recv() => info!("Received SIGTERM, shutting down..."),
+        _ = sigint.recv() => info!("Received SIGINT, shutting down..."),
     }
+
+    if !tasks.stop(SHUTDOWN_TIMEOUT).await {
+        warn!(
+            "Not all tasks stopped within {} seconds",
+            SHUTDOWN_TIMEOUT.as_secs()
+        );
+    }
+
+    db.wal_checkpoint().await
+}
+
+/// Options for `run`, usually taken from the `Config`.
+pub struct RunOptions<P> {
+    pub enable_watcher: bool,
+    pub watcher_url: P,
+    pub connectivity: Connectivity,
+    pub db_vacuum_interval_hours: u64,
+    pub comms_channel_capacity: Option<usize>,
 }
 
 pub async fn run<
@@ -129,19 +426,48 @@ pub async fn run<
     T: Clone + TwitterTransport,
     E: Clone + EmailTransport,
 >(
-    enable_watcher: bool,
-    watcher_url: P,
+    options: RunOptions<P>,
+    tasks: &mut Tasks,
     db2: Database,
     matrix_transport: M,
     twitter_transport: T,
     email_transport: E,
+    webhook: Option<WebhookNotifier>,
+    reminders: Option<ReminderScheduler>,
 ) -> Result<()> {
+    let RunOptions {
+        enable_watcher,
+        watcher_url,
+        connectivity,
+        db_vacuum_interval_hours,
+        comms_channel_capacity,
+    } = options;
+
+    if db_vacuum_interval_hours > 0 {
+        info!("Starting database maintenance task");
+        tasks.spawn(run_db_maintenance(
+            db2.clone(),
+            db_vacuum_interval_hours * 3600,
+            tasks.shutdown(),
+        ));
+    } else {
+        warn!("Database maintenance task is disabled");
+    }
+
+    let mut identity_manager_config = IdentityManagerConfig::default();
+    if let Some(capacity) = comms_channel_capacity {
+        identity_manager_config.comms_channel_capacity = capacity;
+    }
+
     let (_, c_connector) = run_adapters(
+        tasks,
         db2.clone(),
-        Default::default(),
+        identity_manager_config,
         matrix_transport,
         twitter_transport,
         email_transport,
+        webhook,
+        reminders,
     )
     .await?;
 
@@ -154,7 +480,13 @@ pub async fn run<
         loop {
             interval.tick().await;
 
-            if let Ok(con) = Connector::new::<C>(watcher_url.clone(), c_connector.clone()).await {
+            if let Ok(con) = Connector::new::<C>(
+                watcher_url.clone(),
+                c_connector.clone(),
+                connectivity.clone(),
+            )
+            .await
+            {
                 info!("Connecting to Watcher succeeded");
                 connector = con;
                 break;
@@ -163,6 +495,7 @@ pub async fn run<
             }
 
             if counter == 2 {
+                connectivity.transition(ConnectivityEvent::GaveUp);
                 error!("Failed connecting to Watcher, exiting...");
                 exit(1);
             }
@@ -171,8 +504,9 @@ pub async fn run<
         }
 
         info!("Starting Watcher connector task, listening...");
-        tokio::spawn(async move {
-            connector.start::<C>().await;
+        let shutdown = tasks.shutdown();
+        tasks.spawn(async move {
+            connector.start::<C>(shutdown).await;
         });
     } else {
         warn!("Watcher connector task is disabled. Cannot process any requests...");
@@ -181,6 +515,31 @@ pub async fn run<
     Ok(())
 }
 
+/// Periodically vacuums the database and truncates the write-ahead log, which
+/// prevents the database file from growing indefinitely.
+async fn run_db_maintenance(db: Database, interval_secs: u64, shutdown: Shutdown) {
+    let period = Duration::from_secs(interval_secs);
+    let mut interval = time::interval_at(time::Instant::now() + period, period);
+
+    let mut shutdown = shutdown.subscribe();
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.recv() => break,
+        }
+
+        let start = std::time::Instant::now();
+        let _ = db.vacuum().await.map_err(|err| {
+            error!("Failed to vacuum database: {}", err);
+        });
+        let _ = db.wal_checkpoint().await.map_err(|err| {
+            error!("Failed to checkpoint database: {}", err);
+        });
+
+        info!("Database maintenance finished in {:?}", start.elapsed());
+    }
+}
+
 #[cfg(test)]
 pub async fn test_run<
     M: MatrixTransport,
@@ -194,24 +553,32 @@ pub async fn test_run<
     twitter_transport: T,
     email_transport: E,
 ) -> Result<TestRunReturn> {
+    // Tests never shut down the tasks.
+    let mut tasks = Tasks::default();
     let (c_matrix, c_connector) = run_adapters(
+        &mut tasks,
         db2.clone(),
         identity_manager_config,
         matrix_transport,
         twitter_transport,
         email_transport,
+        None,
+        None,
     )
     .await?;
 
-    let mut connector = Connector::new::<ConnectorMocker>(event_manager.clone(), c_connector)
-        .await
-        .unwrap();
+    let mut connector =
+        Connector::new::<ConnectorMocker>(event_manager.clone(), c_connector, Default::default())
+            .await
+            .unwrap();
 
     let (writer, reader) = ConnectorMocker::init(event_manager).await.unwrap();
     connector.set_writer_reader(writer.clone(), reader.clone());
 
     tokio::spawn(async move {
-        connector.start::<ConnectorMocker>().await;
+        connector
+            .start::<ConnectorMocker>(Shutdown::default())
+            .await;
     });
 
     time::delay_for(Duration::from_secs(1)).await;
@@ -233,11 +600,14 @@ async fn run_adapters<
     T: Clone + TwitterTransport,
     E: Clone + EmailTransport,
 >(
+    tasks: &mut Tasks,
     db2: Database,
     identity_manager_config: IdentityManagerConfig,
     mut matrix_transport: M,
     twitter_transport: T,
     email_transport: E,
+    webhook: Option<WebhookNotifier>,
+    reminders: Option<ReminderScheduler>,
 ) -> Result<(CommsMain, CommsVerifier)> {
     info!("Setting up manager");
     let mut manager = IdentityManager::new(db2.clone(), identity_manager_config)?;
@@ -249,6 +619,10 @@ async fn run_adapters<
     let c_matrix = manager.register_comms(AccountType::Matrix);
     let c_twitter = manager.register_comms(AccountType::Twitter);
     let c_email = manager.register_comms(AccountType::Email);
+    let c_webhook = webhook.as_ref().map(|_| manager.register_webhook_comms());
+    let c_scheduler = reminders
+        .as_ref()
+        .map(|_| manager.register_sender_comms("Scheduler"));
 
     // Since the Matrix event emitter runs in the background, the handling of
     // messages must be tested by using this `CommsVerifier` handle and sending
@@ -256,44 +630,65 @@ async fn run_adapters<
     let main_matrix = manager.get_comms(&AccountType::Matrix)?.clone();
 
     info!("Starting manager task");
-    tokio::spawn(async move {
-        manager.start().await;
+    let shutdown = tasks.shutdown();
+    tasks.spawn(async move {
+        manager.start(shutdown).await;
     });
 
     info!("Starting display name handler");
     let l_db = db2.clone();
-    tokio::spawn(async move {
+    let shutdown = tasks.shutdown();
+    tasks.spawn(async move {
         DisplayNameHandler::new(l_db, c_display_name, 0.85)
-            .start()
+            .start(shutdown)
             .await;
     });
 
     info!("Starting Matrix task");
     let l_db = db2.clone();
     let l_c_matrix = c_matrix.clone();
-    tokio::spawn(async move {
+    let shutdown = tasks.shutdown();
+    tasks.spawn(async move {
         matrix_transport.run_emitter(l_db.clone(), c_emitter).await;
 
         MatrixHandler::new(l_db, l_c_matrix, matrix_transport)
-            .start()
+            .start(shutdown)
             .await;
     });
 
     info!("Starting Twitter task");
     let l_db = db2.clone();
-    tokio::spawn(async move {
+    let shutdown = tasks.shutdown();
+    tasks.spawn(async move {
         TwitterHandler::new(l_db, c_twitter)
-            .start(twitter_transport)
+            .start(twitter_transport, shutdown)
             .await;
     });
 
     info!("Starting Email task");
     let l_db = db2.clone();
-    tokio::spawn(async move {
+    let shutdown = tasks.shutdown();
+    tasks.spawn(async move {
         EmailHandler::new(l_db, c_email)
-            .start(email_transport)
+            .start(email_transport, shutdown)
             .await;
     });
 
+    if let (Some(webhook), Some(c_webhook)) = (webhook, c_webhook) {
+        info!("Starting webhook notifier task");
+        let shutdown = tasks.shutdown();
+        tasks.spawn(async move {
+            webhook.start(c_webhook, shutdown).await;
+        });
+    }
+
+    if let (Some(reminders), Some(c_scheduler)) = (reminders, c_scheduler) {
+        info!("Starting challenge reminder task");
+        let shutdown = tasks.shutdown();
+        tasks.spawn(async move {
+            reminders.start(c_scheduler, shutdown).await;
+        });
+    }
+
     Ok((main_matrix, c_connector))
 }
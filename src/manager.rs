@@ -1,4 +1,4 @@
-use crate::comms::{generate_comms, CommsMain, CommsMessage, CommsVerifier};
+use crate::comms::{generate_comms, CommsMain, CommsMessage, CommsVerifier, Shutdown};
 use crate::db::Database;
 use crate::primitives::{
     Account, AccountType, Challenge, ChallengeStatus, Judgement, NetAccount, NetworkAddress, Result,
@@ -81,6 +81,103 @@ impl OnChainIdentity {
     pub fn account_states_mut(&mut self) -> &mut Vec<AccountState> {
         &mut self.accounts
     }
+    /// Partitions the account states into `(valid, invalid, pending)`. Invalid
+    /// accounts need to be updated by the user, pending accounts still await
+    /// a (valid) response to the challenge.
+    pub fn fields_by_status(&self) -> (Vec<&AccountState>, Vec<&AccountState>, Vec<&AccountState>) {
+        let mut valid = vec![];
+        let mut invalid = vec![];
+        let mut pending = vec![];
+
+        for state in &self.accounts {
+            match (&state.account_status, &state.challenge_status) {
+                (AccountStatus::Invalid, _)
+                | (AccountStatus::Notified, _)
+                | (AccountStatus::Unsupported, _)
+                | (_, ChallengeStatus::Rejected) => invalid.push(state),
+                (_, ChallengeStatus::Accepted) => valid.push(state),
+                (_, ChallengeStatus::Unconfirmed) => pending.push(state),
+            }
+        }
+
+        (valid, invalid, pending)
+    }
+    /// Returns the account states which still require verification, meaning
+    /// the account is invalid or the challenge was not (yet) accepted.
+    pub fn missing_fields(&self) -> Vec<&AccountState> {
+        let (valid, _, _) = self.fields_by_status();
+
+        self.accounts
+            .iter()
+            .filter(|state| !valid.contains(state))
+            .collect()
+    }
+    /// Returns the verification progress of the identity as the mean progress
+    /// of all accounts, in the range `[0.0, 1.0]`. Invalid accounts do not
+    /// count as progress, even if their challenge was accepted.
+    pub fn overall_progress(&self) -> f32 {
+        if self.accounts.is_empty() {
+            return 0.0;
+        }
+
+        let (_, invalid, _) = self.fields_by_status();
+        let total: f32 = self
+            .accounts
+            .iter()
+            .filter(|state| !invalid.contains(state))
+            .map(|state| state.challenge_status.progress_fraction())
+            .sum();
+
+        total / self.accounts.len() as f32
+    }
+    /// Creates a Markdown summary of the verification state of each account,
+    /// suitable to be sent to the user.
+    pub fn to_user_message(&self) -> String {
+        let mut message = format!("Verification status of {}:\n", self.net_account().as_str());
+
+        for state in &self.accounts {
+            let is_display_name = state.account_ty == AccountType::DisplayName;
+            let is_fingerprint = state.account_ty == AccountType::PGPFingerprint;
+
+            let (icon, status) = match (&state.account_status, &state.challenge_status) {
+                (AccountStatus::Unsupported, _) => ("❌", "not supported by the registrar"),
+                (AccountStatus::Invalid, _) | (AccountStatus::Notified, _) if is_display_name => {
+                    ("❌", "invalid - similar to an existing display name")
+                }
+                (AccountStatus::Invalid, _) | (AccountStatus::Notified, _) if is_fingerprint => {
+                    ("❌", "invalid - malformed fingerprint")
+                }
+                (AccountStatus::Invalid, _) | (AccountStatus::Notified, _) => {
+                    ("❌", "invalid - could not be reached")
+                }
+                (_, ChallengeStatus::Accepted) => ("✅", "verified"),
+                (_, ChallengeStatus::Rejected) => ("❌", "invalid signature"),
+                (_, ChallengeStatus::Unconfirmed) if is_display_name => ("⏳", "awaiting check"),
+                (_, ChallengeStatus::Unconfirmed) => ("⏳", "awaiting response"),
+            };
+
+            message.push_str(&format!(
+                "\n{} {}: {}",
+                icon,
+                state.account_ty.to_string(),
+                status
+            ));
+        }
+
+        message.push_str(&format!(
+            "\n\nProgress: {:.0}%",
+            self.overall_progress() * 100.0
+        ));
+
+        let (_, invalid, pending) = self.fields_by_status();
+        if !invalid.is_empty() {
+            message.push_str("\n\nPlease update the invalid accounts in the on-chain identity.");
+        } else if pending.is_empty() {
+            message.push_str("\n\nAll accounts have been verified.");
+        }
+
+        message
+    }
     pub fn remove_account_state(&mut self, account_ty: &AccountType) -> Result<()> {
         let pos = self
             .accounts
@@ -169,17 +266,23 @@ impl FromSql for AccountStatus {
 pub struct IdentityManager {
     db: Database,
     comms: CommsTable,
-    _config: IdentityManagerConfig,
+    config: IdentityManagerConfig,
 }
 
 pub struct IdentityManagerConfig {
     _judgement_timeout_limit: u64,
+    /// Capacity of the communication channels to the adapters. Notifications
+    /// sent to a full channel are dropped. The channels to the manager and to
+    /// the Watcher connector carry judgement requests and judgements, so they
+    /// are unbounded.
+    pub comms_channel_capacity: usize,
 }
 
 impl Default for IdentityManagerConfig {
     fn default() -> Self {
         IdentityManagerConfig {
             _judgement_timeout_limit: 28800, // 8h
+            comms_channel_capacity: 1024,
         }
     }
 }
@@ -192,6 +295,9 @@ struct CommsTable {
     listener: Receiver<CommsMessage>,
     // Lookup table for channels to the requested tasks.
     pairs: HashMap<AccountType, CommsMain>,
+    // Channel to the optional webhook notifier, which is not bound to an
+    // account type.
+    webhook: Option<CommsMain>,
 }
 
 impl IdentityManager {
@@ -204,25 +310,55 @@ impl IdentityManager {
                 to_main: tx1.clone(),
                 listener: recv1,
                 pairs: HashMap::new(),
+                webhook: None,
             },
-            _config: config,
+            config: config,
         })
     }
     pub fn register_comms(&mut self, account_ty: AccountType) -> CommsVerifier {
-        let (cm, cv) = generate_comms(self.comms.to_main.clone(), account_ty.clone());
+        let capacity = if account_ty == AccountType::ReservedConnector {
+            None
+        } else {
+            Some(self.config.comms_channel_capacity)
+        };
+
+        let (cm, cv) = generate_comms(
+            self.comms.to_main.clone(),
+            format!("{:?}", account_ty),
+            capacity,
+        );
         self.comms.pairs.insert(account_ty, cm);
         cv
     }
+    pub fn register_webhook_comms(&mut self) -> CommsVerifier {
+        let (cm, cv) = generate_comms(
+            self.comms.to_main.clone(),
+            "Webhook".to_string(),
+            Some(self.config.comms_channel_capacity),
+        );
+        self.comms.webhook = Some(cm);
+        cv
+    }
+    /// Creates a handle for tasks which only send messages to the manager,
+    /// such as the reminder scheduler.
+    pub fn register_sender_comms(&self, name: &str) -> CommsVerifier {
+        let (_, cv) = generate_comms(self.comms.to_main.clone(), name.to_string(), Some(1));
+        cv
+    }
     pub fn get_comms(&self, account_ty: &AccountType) -> StdResult<&CommsMain, ManagerError> {
         self.comms
             .pairs
             .get(account_ty)
             .ok_or(ManagerError::NoHandlerRegistered(account_ty.clone()))
     }
-    pub async fn start(mut self) {
+    pub async fn start(mut self, shutdown: Shutdown) {
         let mut interval = time::interval(Duration::from_millis(10));
+        let mut shutdown = shutdown.subscribe();
         loop {
-            interval.tick().await;
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = shutdown.recv() => break,
+            }
             let _ = self.local().await.map_err(|err| {
                 error!("{}", err);
             });
@@ -239,18 +375,41 @@ impl IdentityManager {
                 }
                 MessageAcknowledged => {}
                 ExistingDisplayNames { accounts } => {
-                    for (account, net_account) in &accounts {
-                        // TODO: Create a function for batch insert
-                        self.db.insert_display_name(&net_account, account).await?;
-                    }
+                    self.db
+                        .insert_display_names(
+                            &accounts
+                                .iter()
+                                .map(|(account, net_account)| (account, net_account))
+                                .collect::<Vec<(&Account, &NetAccount)>>(),
+                        )
+                        .await?;
                 }
-                JudgementGivenAck { net_account: _ } => {
+                JudgementGivenAck { net_account } => {
+                    if !self.db.insert_judgement_log(&net_account).await? {
+                        warn!(
+                            "Received judgement acknowledgement for unknown address: {}",
+                            net_account.to_display_short()
+                        );
+                    }
+
+                    // The webhook notifier is optional.
+                    if let Some(comms) = self.comms.webhook.as_ref() {
+                        comms.notify_judgement_completed(net_account);
+                    }
+
                     /*
                     self.db.remove_identity(&net_account).await?;
                     self.get_comms(&AccountType::Matrix)?
                         .leave_matrix_room(net_account);
                     */
                 }
+                ChallengeReminder {
+                    net_account,
+                    account_ty,
+                } => {
+                    self.handle_challenge_reminder(net_account, account_ty)
+                        .await?
+                }
                 _ => panic!("Received unrecognized message type. Report as a bug"),
             }
         }
@@ -270,10 +429,14 @@ impl IdentityManager {
         for net_account in net_accounts {
             info!(
                 "Notifying Watcher about timed-out judgement request from: {}",
-                net_account.as_str()
+                net_account.to_display_short()
             );
             connector_comms.notify_identity_judgment(net_account.clone(), Judgement::Erroneous);
 
+            if let Some(comms) = self.comms.webhook.as_ref() {
+                comms.notify_identity_judgment(net_account.clone(), Judgement::Erroneous);
+            }
+
             // TODO: Should be done after Watcher confirmation.
             self.db.delete_identity(&net_account).await?;
             self.get_comms(&AccountType::Matrix)?
@@ -286,7 +449,7 @@ impl IdentityManager {
     async fn handle_new_judgment_request(&mut self, mut ident: OnChainIdentity) -> Result<()> {
         debug!(
             "Handling new judgment request for account: {}",
-            ident.net_account().as_str()
+            ident.net_account()
         );
 
         // Check the current, associated addresses of the identity, if any.
@@ -300,10 +463,19 @@ impl IdentityManager {
         let mut to_delete = vec![];
 
         // Find duplicates.
-        let address = ident.net_account().as_str().to_string();
+        let short = ident.net_account().to_display_short();
         for state in ident.account_states_mut() {
-            // Reject the entire judgment request if a non-white listed account type is specified.
-            if !WHITELIST.contains(&state.account_ty) {
+            if !state.account_ty.is_valid_account(&state.account) {
+                warn!(
+                    "Identity {} specified a malformed {}: {}",
+                    short,
+                    state.account_ty,
+                    state.account.as_str()
+                );
+
+                state.account_status = AccountStatus::Invalid;
+            } else if !WHITELIST.contains(&state.account_ty) {
+                // Reject the entire judgment request if a non-white listed account type is specified.
                 // If the user was already notified about the invalidity, then just ignore this.
                 if existing_accounts
                     .iter()
@@ -316,7 +488,7 @@ impl IdentityManager {
                 {
                     warn!(
                         "Reject identity {}, use of unacceptable account type: {:?}",
-                        address, state.account_ty
+                        short, state.account_ty
                     );
 
                     state.account_status = AccountStatus::Unsupported;
@@ -368,39 +540,96 @@ impl IdentityManager {
                 self.db.reset_init_message(&state.account).await?;
             }
 
-            if state.account_status == AccountStatus::Unsupported {
+            if state.account_status == AccountStatus::Unsupported
+                || state.account_status == AccountStatus::Invalid
+            {
                 continue;
             }
 
-            self.get_comms(&state.account_ty).map(|comms| {
-                comms
-                    .notify_account_verification(ident.net_account().clone(), state.account.clone())
-            })?;
+            self.get_comms(&state.account_ty)?
+                .notify_account_verification(ident.net_account().clone(), state.account.clone())
+                .await;
         }
 
         Ok(())
     }
-    async fn handle_status_change(&mut self, net_account: NetAccount) -> Result<()> {
+    /// Sends the challenge of a pending account again, via the corresponding
+    /// adapter.
+    async fn handle_challenge_reminder(
+        &mut self,
+        net_account: NetAccount,
+        account_ty: AccountType,
+    ) -> Result<()> {
+        // The identity might have been frozen since the reminder was
+        // scheduled.
+        if self.db.is_frozen(&net_account).await? {
+            debug!(
+                "Skipping challenge reminder of frozen identity: {}",
+                net_account
+            );
+            return Ok(());
+        }
+
+        let account = match self
+            .db
+            .select_account_from_net_account(&net_account, &account_ty)
+            .await?
+        {
+            Some(account) => account,
+            None => return Ok(()),
+        };
+
         debug!(
-            "Handling status change for account: {}",
-            net_account.as_str()
+            "Sending challenge reminder to {} ({}) of {}",
+            account.as_str(),
+            account_ty,
+            net_account
         );
 
+        // The Twitter adapter only sends the initial message once.
+        if account_ty == AccountType::Twitter {
+            self.db.reset_init_message(&account).await?;
+        }
+
+        self.get_comms(&account_ty)?
+            .notify_account_verification(net_account, account)
+            .await;
+
+        Ok(())
+    }
+    async fn handle_status_change(&mut self, net_account: NetAccount) -> Result<()> {
+        debug!("Handling status change for account: {}", net_account);
+
+        // The judgement is issued once the identity gets unfrozen, since the
+        // Watcher resends pending judgement requests every minute.
+        if self.db.is_frozen(&net_account).await? {
+            debug!("Skipping status change of frozen identity: {}", net_account);
+            return Ok(());
+        }
+
         if self.db.is_fully_verified(&net_account).await? {
             self.db.persist_display_name(&net_account).await?;
+            self.db
+                .insert_submitted_judgement(&net_account, &Judgement::Reasonable)
+                .await?;
 
             self.get_comms(&AccountType::ReservedConnector)
                 .map(|comms| {
                     info!(
                         "Notifying Watcher about fully verified address: {}",
-                        net_account.as_str()
+                        net_account.to_display_short()
                     );
 
                     comms.notify_identity_judgment(net_account.clone(), Judgement::Reasonable);
                 })?;
 
+            // The webhook notifier is optional.
+            if let Some(comms) = self.comms.webhook.as_ref() {
+                comms.notify_identity_judgment(net_account.clone(), Judgement::Reasonable);
+            }
+
             self.get_comms(&AccountType::Matrix).map(|comms| {
-                debug!("Closing Matrix room for {}", net_account.as_str());
+                debug!("Closing Matrix room for {}", net_account);
                 comms.leave_matrix_room(net_account.clone());
             })?;
 
@@ -464,19 +693,301 @@ impl IdentityManager {
         // to inform that person about the current state of invalid accounts.
         let invalid_accounts = find_invalid(&account_statuses);
         if !invalid_accounts.is_empty() {
+            if let Some(comms) = self.comms.webhook.as_ref() {
+                comms.notify_verification_failed(
+                    net_account.clone(),
+                    invalid_accounts
+                        .iter()
+                        .map(|(account_ty, _, _)| account_ty.clone())
+                        .collect(),
+                );
+            }
+
             if let Some((to_notify, account)) = find_valid(&account_statuses) {
-                self.get_comms(to_notify).map(|comms| {
-                    comms.notify_invalid_accounts(
+                self.get_comms(to_notify)?
+                    .notify_invalid_accounts(
                         net_account.clone(),
                         account.clone(),
                         invalid_accounts.clone(),
-                    );
-                })?;
+                    )
+                    .await;
             } else {
-                warn!("Identity {} could not be informed about invalid accounts (no valid accounts yet)", net_account.as_str());
+                warn!("Identity {} could not be informed about invalid accounts (no valid accounts yet)", net_account.to_display_short());
             }
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::de::DeserializeOwned;
+    use serde::Serialize;
+    use std::fmt::Debug;
+
+    fn assert_round_trip<T: Serialize + DeserializeOwned + Eq + Debug>(val: T) {
+        let json = serde_json::to_string(&val).unwrap();
+        assert_eq!(serde_json::from_str::<T>(&json).unwrap(), val);
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        use AccountStatus::*;
+        use ChallengeStatus::*;
+
+        let mut ident = OnChainIdentity::new(NetAccount::alice()).unwrap();
+        ident
+            .push_account(AccountType::Matrix, Account::from("@alice:matrix.org"))
+            .unwrap();
+        ident
+            .push_account(AccountType::Email, Account::from("alice@example.com"))
+            .unwrap();
+
+        for state in ident.account_states_mut() {
+            state.account_status = Valid;
+            state.challenge_status = Accepted;
+            state.skip_inform = true;
+        }
+
+        assert_round_trip(ident.network_address.clone());
+        for state in ident.account_states() {
+            assert_round_trip(state.clone());
+        }
+        assert_round_trip(ident);
+
+        for status in vec![Unknown, Valid, Invalid, Notified, Unsupported] {
+            assert_round_trip(status);
+        }
+
+        for status in vec![Unconfirmed, Accepted, Rejected] {
+            assert_round_trip(status);
+        }
+    }
+
+    #[test]
+    fn missing_fields() {
+        let mut ident = OnChainIdentity::new(NetAccount::alice()).unwrap();
+
+        ident
+            .push_account(AccountType::DisplayName, Account::from("Alice"))
+            .unwrap();
+        ident
+            .push_account(AccountType::Matrix, Account::from("@alice:matrix.org"))
+            .unwrap();
+        ident
+            .push_account(AccountType::Email, Account::from("alice@example.com"))
+            .unwrap();
+        ident
+            .push_account(AccountType::Twitter, Account::from("@alice"))
+            .unwrap();
+
+        for state in ident.account_states_mut() {
+            match state.account_ty {
+                AccountType::DisplayName | AccountType::Matrix => {
+                    state.challenge_status = ChallengeStatus::Accepted
+                }
+                AccountType::Email => state.challenge_status = ChallengeStatus::Rejected,
+                _ => {}
+            }
+        }
+
+        let missing = ident
+            .missing_fields()
+            .into_iter()
+            .map(|state| state.account_ty.clone())
+            .collect::<Vec<AccountType>>();
+
+        assert_eq!(missing, vec![AccountType::Email, AccountType::Twitter]);
+    }
+
+    #[test]
+    fn fields_by_status() {
+        let mut ident = OnChainIdentity::new(NetAccount::alice()).unwrap();
+
+        ident
+            .push_account(AccountType::DisplayName, Account::from("Alice"))
+            .unwrap();
+        ident
+            .push_account(AccountType::Matrix, Account::from("@alice:matrix.org"))
+            .unwrap();
+        ident
+            .push_account(AccountType::Email, Account::from("alice@example.com"))
+            .unwrap();
+        ident
+            .push_account(AccountType::Twitter, Account::from("@alice"))
+            .unwrap();
+        ident
+            .push_account(AccountType::Web, Account::from("alice.com"))
+            .unwrap();
+        ident
+            .push_account(AccountType::LegalName, Account::from("Alice Doe"))
+            .unwrap();
+
+        for state in ident.account_states_mut() {
+            match state.account_ty {
+                AccountType::DisplayName => {
+                    state.account_status = AccountStatus::Valid;
+                    state.challenge_status = ChallengeStatus::Accepted;
+                }
+                AccountType::Matrix => state.challenge_status = ChallengeStatus::Accepted,
+                AccountType::Email => state.account_status = AccountStatus::Notified,
+                AccountType::Twitter => state.challenge_status = ChallengeStatus::Rejected,
+                AccountType::Web => state.account_status = AccountStatus::Valid,
+                AccountType::LegalName => {}
+                _ => panic!(),
+            }
+        }
+
+        let account_tys = |states: Vec<&AccountState>| {
+            states
+                .into_iter()
+                .map(|state| state.account_ty.clone())
+                .collect::<Vec<AccountType>>()
+        };
+
+        let (valid, invalid, pending) = ident.fields_by_status();
+        assert_eq!(
+            account_tys(valid),
+            vec![AccountType::DisplayName, AccountType::Matrix]
+        );
+        assert_eq!(
+            account_tys(invalid),
+            vec![AccountType::Email, AccountType::Twitter]
+        );
+        assert_eq!(
+            account_tys(pending),
+            vec![AccountType::Web, AccountType::LegalName]
+        );
+
+        assert_eq!(
+            account_tys(ident.missing_fields()),
+            vec![
+                AccountType::Email,
+                AccountType::Twitter,
+                AccountType::Web,
+                AccountType::LegalName
+            ]
+        );
+    }
+
+    #[test]
+    fn overall_progress() {
+        let mut ident = OnChainIdentity::new(NetAccount::alice()).unwrap();
+        assert_eq!(ident.overall_progress(), 0.0);
+
+        ident
+            .push_account(AccountType::DisplayName, Account::from("Alice"))
+            .unwrap();
+        ident
+            .push_account(AccountType::Matrix, Account::from("@alice:matrix.org"))
+            .unwrap();
+        ident
+            .push_account(AccountType::Email, Account::from("alice@example.com"))
+            .unwrap();
+        ident
+            .push_account(AccountType::Twitter, Account::from("@alice"))
+            .unwrap();
+        assert_eq!(ident.overall_progress(), 0.0);
+
+        for state in ident.account_states_mut() {
+            match state.account_ty {
+                AccountType::Matrix => state.challenge_status = ChallengeStatus::Accepted,
+                AccountType::Email => state.challenge_status = ChallengeStatus::Rejected,
+                // Accepted, but the account itself is invalid.
+                AccountType::Twitter => {
+                    state.challenge_status = ChallengeStatus::Accepted;
+                    state.account_status = AccountStatus::Invalid;
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(ident.overall_progress(), 0.25);
+
+        for state in ident.account_states_mut() {
+            state.account_status = AccountStatus::Valid;
+            state.challenge_status = ChallengeStatus::Accepted;
+        }
+        assert_eq!(ident.overall_progress(), 1.0);
+    }
+
+    #[test]
+    fn to_user_message() {
+        let mut ident = OnChainIdentity::new(NetAccount::alice()).unwrap();
+
+        ident
+            .push_account(AccountType::DisplayName, Account::from("Alice"))
+            .unwrap();
+        ident
+            .push_account(AccountType::Matrix, Account::from("@alice:matrix.org"))
+            .unwrap();
+        ident
+            .push_account(AccountType::Email, Account::from("alice@example.com"))
+            .unwrap();
+        ident
+            .push_account(AccountType::Twitter, Account::from("@alice"))
+            .unwrap();
+        ident
+            .push_account(AccountType::Web, Account::from("alice.com"))
+            .unwrap();
+
+        for state in ident.account_states_mut() {
+            match state.account_ty {
+                AccountType::DisplayName => state.account_status = AccountStatus::Invalid,
+                AccountType::Matrix => state.challenge_status = ChallengeStatus::Accepted,
+                AccountType::Twitter => state.challenge_status = ChallengeStatus::Rejected,
+                AccountType::Web => state.account_status = AccountStatus::Unsupported,
+                _ => {}
+            }
+        }
+
+        assert_eq!(
+            ident.to_user_message(),
+            "\
+            Verification status of 14GcE3qBiEnAyg2sDfadT3fQhWd2Z3M59tWi1CvVV8UwxUfU:\n\
+            \n❌ Display Name: invalid - similar to an existing display name\
+            \n✅ Matrix: verified\
+            \n⏳ Email: awaiting response\
+            \n❌ Twitter: invalid signature\
+            \n❌ Web: not supported by the registrar\
+            \n\nProgress: 20%\
+            \n\nPlease update the invalid accounts in the on-chain identity.\
+        "
+        );
+
+        // Invalid accounts other than display names, pending display names.
+        for state in ident.account_states_mut() {
+            match state.account_ty {
+                AccountType::DisplayName => state.account_status = AccountStatus::Unknown,
+                AccountType::Email => state.account_status = AccountStatus::Notified,
+                _ => {}
+            }
+        }
+
+        let message = ident.to_user_message();
+        assert!(message.contains("\n⏳ Display Name: awaiting check"));
+        assert!(message.contains("\n❌ Email: invalid - could not be reached"));
+        assert!(!message.contains("All accounts have been verified."));
+
+        // Fully verified.
+        ident.remove_account_state(&AccountType::Web).unwrap();
+        for state in ident.account_states_mut() {
+            state.account_status = AccountStatus::Valid;
+            state.challenge_status = ChallengeStatus::Accepted;
+        }
+
+        assert_eq!(
+            ident.to_user_message(),
+            "\
+            Verification status of 14GcE3qBiEnAyg2sDfadT3fQhWd2Z3M59tWi1CvVV8UwxUfU:\n\
+            \n✅ Display Name: verified\
+            \n✅ Matrix: verified\
+            \n✅ Email: verified\
+            \n✅ Twitter: verified\
+            \n\nProgress: 100%\
+            \n\nAll accounts have been verified.\
+        "
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::{HeaderName, HeaderValue};
+use actix_web::Error;
+use futures::future::{ok, LocalBoxFuture, Ready};
+use log::Level;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: &'static str = "x-request-id";
+/// Polled frequently by monitoring, so only logged at debug level.
+const HEALTHCHECK_PATH: &'static str = "/healthcheck";
+
+/// Logs the method, path, status code and duration of every request. Each
+/// request is assigned an ID, which is included in the log line and returned
+/// in the `X-Request-Id` header.
+pub struct RequestLogger;
+
+impl<S, B> Transform<S> for RequestLogger
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestLoggerMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequestLoggerMiddleware { service: service })
+    }
+}
+
+pub struct RequestLoggerMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service for RequestLoggerMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let request_id = Uuid::new_v4().to_hyphenated().to_string();
+        let method = req.method().to_string();
+        let path = req.path().to_string();
+        let start = Instant::now();
+
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let mut res = fut.await?;
+
+            let level = if path == HEALTHCHECK_PATH {
+                Level::Debug
+            } else {
+                Level::Info
+            };
+
+            log!(
+                level,
+                "request_id={} method={} path={} status_code={} duration_ms={}",
+                request_id,
+                method,
+                path,
+                res.status().as_u16(),
+                start.elapsed().as_millis()
+            );
+
+            if let Ok(value) = HeaderValue::from_str(&request_id) {
+                res.headers_mut()
+                    .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+            }
+
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{get, rt, test, App, HttpResponse, Responder};
+
+    #[get("/test")]
+    async fn endpoint() -> impl Responder {
+        HttpResponse::Ok().finish()
+    }
+
+    #[test]
+    fn request_id_header() {
+        let mut sys = rt::System::new("request logger test");
+        sys.block_on(async {
+            let mut app =
+                test::init_service(App::new().wrap(RequestLogger).service(endpoint)).await;
+
+            let req = test::TestRequest::get().uri("/test").to_request();
+            let res = test::call_service(&mut app, req).await;
+            assert!(res.status().is_success());
+
+            let request_id = res
+                .headers()
+                .get(REQUEST_ID_HEADER)
+                .unwrap()
+                .to_str()
+                .unwrap();
+            assert!(Uuid::parse_str(request_id).is_ok());
+
+            // Also set for failed requests.
+            let req = test::TestRequest::get().uri("/unknown").to_request();
+            let res = test::call_service(&mut app, req).await;
+            assert_eq!(res.status().as_u16(), 404);
+            assert!(res.headers().contains_key(REQUEST_ID_HEADER));
+        });
+    }
+}
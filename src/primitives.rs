@@ -1,4 +1,5 @@
 use base58::FromBase58;
+use blake2::{Blake2b, Digest};
 use failure::err_msg;
 
 use rand::{thread_rng, Rng};
@@ -10,6 +11,7 @@ use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::convert::TryFrom;
 use std::fmt::{self, Debug, Display};
 use std::result::Result as StdResult;
+use std::str::FromStr;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 pub type Result<T> = StdResult<T, failure::Error>;
@@ -89,10 +91,12 @@ impl From<&SchnorrkelPubKey> for NetAccount {
     fn from(value: &SchnorrkelPubKey) -> Self {
         use base58::ToBase58;
 
-        // The address here is technically invalid, but it contains enough
-        // information in order to extract a public key out of it. So for
-        // testing this is sufficient.
-        NetAccount::from(format!("1{}", value.to_bytes().to_base58()))
+        let mut bytes = vec![AddressFormat::Polkadot.prefix()];
+        bytes.extend_from_slice(&value.to_bytes());
+        let checksum = ss58_checksum(&bytes);
+        bytes.extend_from_slice(&checksum);
+
+        NetAccount::from(bytes.to_base58())
     }
 }
 
@@ -120,6 +124,20 @@ impl NetAccount {
     pub fn as_str(&self) -> &str {
         self.0.as_str()
     }
+    /// Returns the first six and the last four characters of the address,
+    /// e.g. `14GcE3…xUfU`. Use `Display` for the full address.
+    pub fn to_display_short(&self) -> String {
+        let chars: Vec<char> = self.0.chars().collect();
+        if chars.len() <= 10 {
+            return self.0.clone();
+        }
+
+        format!(
+            "{}…{}",
+            chars[..6].iter().collect::<String>(),
+            chars[chars.len() - 4..].iter().collect::<String>()
+        )
+    }
     #[cfg(test)]
     pub fn alice() -> Self {
         NetAccount::from("14GcE3qBiEnAyg2sDfadT3fQhWd2Z3M59tWi1CvVV8UwxUfU")
@@ -146,6 +164,12 @@ impl From<&str> for NetAccount {
     }
 }
 
+impl fmt::Display for NetAccount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
 #[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub struct Account(String);
 
@@ -193,10 +217,58 @@ impl fmt::Display for Account {
     }
 }
 
+/// The SS58 address format, identified by the network prefix of an address.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum AddressFormat {
+    #[serde(rename = "polkadot")]
+    Polkadot,
+    #[serde(rename = "kusama")]
+    Kusama,
+    #[serde(rename = "generic")]
+    Generic,
+}
+
+impl AddressFormat {
+    pub fn from_prefix(prefix: u8) -> Option<Self> {
+        match prefix {
+            0 => Some(AddressFormat::Polkadot),
+            2 => Some(AddressFormat::Kusama),
+            42 => Some(AddressFormat::Generic),
+            _ => None,
+        }
+    }
+    pub fn prefix(&self) -> u8 {
+        match self {
+            AddressFormat::Polkadot => 0,
+            AddressFormat::Kusama => 2,
+            AddressFormat::Generic => 42,
+        }
+    }
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AddressFormat::Polkadot => "polkadot",
+            AddressFormat::Kusama => "kusama",
+            AddressFormat::Generic => "generic",
+        }
+    }
+}
+
+/// Computes the two byte SS58 checksum over the network prefix and the public
+/// key.
+fn ss58_checksum(payload: &[u8]) -> [u8; 2] {
+    let mut hasher = Blake2b::new();
+    hasher.update(b"SS58PRE");
+    hasher.update(payload);
+
+    let hash = hasher.finalize();
+    [hash[0], hash[1]]
+}
+
 /// The Polkadot/Kusama address including the extracted public key.
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct NetworkAddress {
     address: NetAccount,
+    address_format: AddressFormat,
     pub_key: PubKey,
 }
 
@@ -204,11 +276,20 @@ impl NetworkAddress {
     pub fn address(&self) -> &NetAccount {
         &self.address
     }
+    pub fn address_format(&self) -> AddressFormat {
+        self.address_format
+    }
     pub fn pub_key(&self) -> &PubKey {
         &self.pub_key
     }
 }
 
+impl fmt::Display for NetworkAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.address)
+    }
+}
+
 impl TryFrom<NetAccount> for NetworkAddress {
     type Error = failure::Error;
 
@@ -218,12 +299,23 @@ impl TryFrom<NetAccount> for NetworkAddress {
             .from_base58()
             .map_err(|_| err_msg("failed to decode address from base58"))?;
 
-        if bytes.len() < 33 {
-            return Err(err_msg("invalid address"));
+        // Only single byte network prefixes are supported: the prefix, the
+        // 32 byte public key and the two byte checksum.
+        if bytes.len() != 35 {
+            return Err(err_msg("invalid address length"));
         }
 
+        if ss58_checksum(&bytes[..33]) != bytes[33..] {
+            warn!("Rejecting address with invalid checksum: {}", value);
+            return Err(err_msg("invalid address checksum"));
+        }
+
+        let address_format = AddressFormat::from_prefix(bytes[0])
+            .ok_or_else(|| err_msg("unsupported address format"))?;
+
         Ok(NetworkAddress {
             address: value,
+            address_format: address_format,
             pub_key: PubKey::try_from(bytes[1..33].to_vec())?,
         })
     }
@@ -257,6 +349,60 @@ pub enum AccountType {
     ReservedEmitter,
 }
 
+impl AccountType {
+    /// All account types which are stored in the database. Excludes the
+    /// reserved types used for internal communication.
+    pub const DB_TYPES: [AccountType; 9] = [
+        AccountType::LegalName,
+        AccountType::DisplayName,
+        AccountType::Email,
+        AccountType::Web,
+        AccountType::Twitter,
+        AccountType::Matrix,
+        AccountType::PGPFingerprint,
+        AccountType::Image,
+        AccountType::Additional,
+    ];
+
+    /// The canonical name of the account type as used in the database.
+    /// Returns `None` for reserved types.
+    pub fn as_db_str(&self) -> Option<&'static str> {
+        use AccountType::*;
+
+        match self {
+            LegalName => Some("legal_name"),
+            DisplayName => Some("display_name"),
+            Email => Some("email"),
+            Web => Some("web"),
+            Twitter => Some("twitter"),
+            Matrix => Some("matrix"),
+            PGPFingerprint => Some("pgp_fingerprint"),
+            Image => Some("image"),
+            Additional => Some("additional"),
+            ReservedConnector | ReservedEmitter => None,
+        }
+    }
+    /// Checks whether the account has a valid format for this account type.
+    /// Only PGP fingerprints are checked (V4 with 40 hex characters, V3 with
+    /// 32, optionally prefixed with "0x"), any other account is verified by
+    /// its corresponding adapter.
+    pub fn is_valid_account(&self, account: &Account) -> bool {
+        match self {
+            AccountType::PGPFingerprint => {
+                let value = account.as_str();
+                let value = value
+                    .strip_prefix("0x")
+                    .or_else(|| value.strip_prefix("0X"))
+                    .unwrap_or(value);
+
+                (value.len() == 40 || value.len() == 32)
+                    && value.chars().all(|c| c.is_ascii_hexdigit())
+            }
+            _ => true,
+        }
+    }
+}
+
 impl Display for AccountType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use AccountType::*;
@@ -279,41 +425,35 @@ impl Display for AccountType {
 
 impl ToSql for AccountType {
     fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
-        use AccountType::*;
-        use ToSqlOutput::*;
-        use ValueRef::*;
+        self.as_db_str()
+            .map(|s| ToSqlOutput::Borrowed(ValueRef::Text(s.as_bytes())))
+            .ok_or(rusqlite::Error::InvalidQuery)
+    }
+}
 
-        match self {
-            LegalName => Ok(Borrowed(Text(b"legal_name"))),
-            DisplayName => Ok(Borrowed(Text(b"display_name"))),
-            Email => Ok(Borrowed(Text(b"email"))),
-            Web => Ok(Borrowed(Text(b"web"))),
-            Twitter => Ok(Borrowed(Text(b"twitter"))),
-            Matrix => Ok(Borrowed(Text(b"matrix"))),
-            PGPFingerprint => Ok(Borrowed(Text(b"pgp_fingerprint"))),
-            Image => Ok(Borrowed(Text(b"image"))),
-            Additional => Ok(Borrowed(Text(b"additional"))),
-            ReservedConnector => Err(rusqlite::Error::InvalidQuery),
-            ReservedEmitter => Err(rusqlite::Error::InvalidQuery),
-        }
+impl FromStr for AccountType {
+    type Err = failure::Error;
+
+    /// Parses the canonical name of the account type (see
+    /// `AccountType::as_db_str`), ignoring case. Reserved types can not be
+    /// parsed.
+    fn from_str(s: &str) -> Result<Self> {
+        AccountType::DB_TYPES
+            .iter()
+            .find(|ty| matches!(ty.as_db_str(), Some(name) if name.eq_ignore_ascii_case(s.trim())))
+            .cloned()
+            .ok_or_else(|| err_msg(format!("unknown account type: '{}'", s)))
     }
 }
 
 impl FromSql for AccountType {
     fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
         match value {
-            ValueRef::Text(val) => match val {
-                b"legal_name" => Ok(AccountType::LegalName),
-                b"display_name" => Ok(AccountType::DisplayName),
-                b"email" => Ok(AccountType::Email),
-                b"web" => Ok(AccountType::Web),
-                b"twitter" => Ok(AccountType::Twitter),
-                b"matrix" => Ok(AccountType::Matrix),
-                b"pgp_fingerprint" => Ok(AccountType::PGPFingerprint),
-                b"image" => Ok(AccountType::Image),
-                b"additional" => Ok(AccountType::Additional),
-                _ => Err(FromSqlError::InvalidType),
-            },
+            ValueRef::Text(val) => AccountType::DB_TYPES
+                .iter()
+                .find(|ty| ty.as_db_str().map(|s| s.as_bytes()) == Some(val))
+                .cloned()
+                .ok_or(FromSqlError::InvalidType),
             _ => Err(FromSqlError::InvalidType),
         }
     }
@@ -329,6 +469,16 @@ pub enum ChallengeStatus {
     Rejected,
 }
 
+impl ChallengeStatus {
+    /// Verification progress of a single challenge, in the range `[0.0, 1.0]`.
+    pub fn progress_fraction(&self) -> f32 {
+        match self {
+            ChallengeStatus::Accepted => 1.0,
+            ChallengeStatus::Unconfirmed | ChallengeStatus::Rejected => 0.0,
+        }
+    }
+}
+
 impl ToSql for ChallengeStatus {
     fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
         use ChallengeStatus::*;
@@ -389,6 +539,32 @@ pub enum Judgement {
     Erroneous,
 }
 
+impl ToSql for Judgement {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        use Judgement::*;
+        use ToSqlOutput::*;
+        use ValueRef::*;
+
+        match self {
+            Reasonable => Ok(Borrowed(Text(b"reasonable"))),
+            Erroneous => Ok(Borrowed(Text(b"erroneous"))),
+        }
+    }
+}
+
+impl FromSql for Judgement {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value {
+            ValueRef::Text(val) => match val {
+                b"reasonable" => Ok(Judgement::Reasonable),
+                b"erroneous" => Ok(Judgement::Erroneous),
+                _ => Err(FromSqlError::InvalidType),
+            },
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
 pub trait Fatal<T> {
     fn fatal(self) -> T;
 }
@@ -409,3 +585,192 @@ impl<T: Debug> Fatal<T> for Option<T> {
         self.expect("Fatal error encountered. Report as a bug.")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_addresses() {
+        let net_account = NetAccount::alice();
+        assert_eq!(
+            net_account.to_string(),
+            "14GcE3qBiEnAyg2sDfadT3fQhWd2Z3M59tWi1CvVV8UwxUfU"
+        );
+
+        let network_address = NetworkAddress::try_from(net_account).unwrap();
+        assert_eq!(
+            format!("address: {}", network_address),
+            "address: 14GcE3qBiEnAyg2sDfadT3fQhWd2Z3M59tWi1CvVV8UwxUfU"
+        );
+    }
+
+    #[test]
+    fn valid_pgp_fingerprint() {
+        const HEX: &[u8] = b"0123456789abcdefABCDEF";
+        let mut rng = thread_rng();
+        let ty = AccountType::PGPFingerprint;
+
+        let mut random_hex = |len: usize| -> String {
+            (0..len)
+                .map(|_| HEX[rng.gen_range(0, HEX.len())] as char)
+                .collect()
+        };
+
+        for _ in 0..100 {
+            assert!(ty.is_valid_account(&Account::from(random_hex(40))));
+            assert!(ty.is_valid_account(&Account::from(random_hex(32))));
+            assert!(ty.is_valid_account(&Account::from(format!("0x{}", random_hex(40)))));
+            assert!(ty.is_valid_account(&Account::from(format!("0X{}", random_hex(32)))));
+        }
+
+        assert!(!ty.is_valid_account(&Account::from("0x")));
+        assert!(!ty.is_valid_account(&Account::from(format!("0x0x{}", random_hex(40)))));
+
+        // Invalid length.
+        for &len in &[0, 16, 31, 33, 39, 41, 64] {
+            assert!(!ty.is_valid_account(&Account::from(random_hex(len))));
+        }
+
+        // Invalid characters.
+        for &c in &["g", "Z", " ", "-", "ä"] {
+            let mut value = random_hex(39);
+            value.push_str(c);
+            assert!(!ty.is_valid_account(&Account::from(value)));
+        }
+
+        assert!(!ty.is_valid_account(&Account::from("alice@example.com")));
+
+        // Other account types are not checked.
+        assert!(AccountType::Email.is_valid_account(&Account::from("alice@example.com")));
+    }
+
+    #[test]
+    fn display_short_address() {
+        assert_eq!(NetAccount::alice().to_display_short(), "14GcE3…xUfU");
+        assert_eq!(NetAccount::bob().to_display_short(), "163AnE…TU5C");
+        assert_eq!(NetAccount::from("short").to_display_short(), "short");
+        assert_eq!(
+            NetAccount::alice().to_string(),
+            "14GcE3qBiEnAyg2sDfadT3fQhWd2Z3M59tWi1CvVV8UwxUfU"
+        );
+    }
+
+    #[test]
+    fn network_address_from_ss58() {
+        for net_account in &[NetAccount::alice(), NetAccount::bob(), NetAccount::eve()] {
+            let network_address = NetworkAddress::try_from(net_account.clone()).unwrap();
+            assert_eq!(network_address.address_format(), AddressFormat::Polkadot);
+        }
+
+        // Kusama address.
+        let network_address = NetworkAddress::try_from(NetAccount::from(
+            "HNZata7iMYWmk5RvZRTiAsSDhV8366zq2YGb3tLH5Upf74F",
+        ))
+        .unwrap();
+        assert_eq!(network_address.address_format(), AddressFormat::Kusama);
+
+        // The format is part of the serialized address.
+        let value = serde_json::to_value(&network_address).unwrap();
+        assert_eq!(value["address_format"], "kusama");
+        assert_eq!(
+            serde_json::from_value::<NetworkAddress>(value).unwrap(),
+            network_address
+        );
+    }
+
+    #[test]
+    fn network_address_from_ss58_round_trip() {
+        use schnorrkel::Keypair;
+
+        for _ in 0..50 {
+            let keypair = Keypair::generate();
+            let network_address =
+                NetworkAddress::try_from(NetAccount::from(&keypair.public)).unwrap();
+
+            assert_eq!(network_address.address_format(), AddressFormat::Polkadot);
+            assert_eq!(
+                network_address.pub_key().to_bytes(),
+                keypair.public.to_bytes()
+            );
+        }
+    }
+
+    #[test]
+    fn network_address_from_invalid_ss58() {
+        let invalid = [
+            // Not base58 ('0' is not part of the alphabet).
+            "04GcE3qBiEnAyg2sDfadT3fQhWd2Z3M59tWi1CvVV8UwxUfU",
+            // Truncated.
+            "14GcE3qBiEnAyg2sDfadT3fQhWd2Z3M59tWi1CvVV8Uw",
+            // Corrupted checksum.
+            "14GcE3qBiEnAyg2sDfadT3fQhWd2Z3M59tWi1CvVV8UwxUfT",
+            // Corrupted public key.
+            "14GcE3qBiEnAyg3GZGZjkLNbAPSTh8NVVCzqGzYzk5CBYJyp",
+            // Kusama prefix with the checksum of a Polkadot address.
+            "Fqvk2uzUpXdHnqo2jLgCrCFzUucfQc7XmcyEaD6QqfvX89G",
+            // Empty.
+            "",
+        ];
+
+        for address in &invalid {
+            assert!(NetworkAddress::try_from(NetAccount::from(*address)).is_err());
+        }
+    }
+
+    #[test]
+    fn account_type_from_str() {
+        for account_ty in &AccountType::DB_TYPES {
+            let name = account_ty.as_db_str().unwrap();
+            assert_eq!(&AccountType::from_str(name).unwrap(), account_ty);
+        }
+
+        assert_eq!(
+            AccountType::from_str("Matrix").unwrap(),
+            AccountType::Matrix
+        );
+        assert_eq!(
+            AccountType::from_str(" EMAIL ").unwrap(),
+            AccountType::Email
+        );
+        assert_eq!(
+            "pgp_fingerprint".parse::<AccountType>().unwrap(),
+            AccountType::PGPFingerprint
+        );
+
+        // Invalid names.
+        for name in &[
+            "",
+            "telegram",
+            "Display Name",
+            "pgpFingerprint",
+            "matrix:@alice:matrix.org",
+            "reserved_connector",
+            "ReservedConnector",
+        ] {
+            assert!(AccountType::from_str(name).is_err());
+        }
+    }
+
+    #[test]
+    fn account_type_db_str_round_trip() {
+        for account_ty in &AccountType::DB_TYPES {
+            let output = account_ty.to_sql().unwrap();
+            let value = match output {
+                ToSqlOutput::Borrowed(value) => value,
+                _ => panic!("unexpected SQL output"),
+            };
+
+            assert_eq!(&AccountType::column_result(value).unwrap(), account_ty);
+        }
+
+        let reserved = [AccountType::ReservedConnector, AccountType::ReservedEmitter];
+
+        for account_ty in &reserved {
+            assert!(account_ty.as_db_str().is_none());
+            assert!(account_ty.to_sql().is_err());
+        }
+
+        assert!(AccountType::column_result(ValueRef::Text(b"unknown")).is_err());
+    }
+}
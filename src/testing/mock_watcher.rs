@@ -0,0 +1,178 @@
+use crate::connector::{EventType, JudgementGiven, JudgementResponse, Message};
+use crate::primitives::Result;
+use futures::sink::SinkExt;
+use futures::stream::StreamExt;
+use futures_channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{Mutex, RwLock};
+use tokio_tungstenite::{accept_async, WebSocketStream};
+use tungstenite::protocol::Message as TungMessage;
+
+/// A minimal Watcher which allows running the bot without a Substrate node.
+/// It answers display name and pending judgement requests with empty lists,
+/// acknowledges judgements and forwards events injected with
+/// `MockWatcher::push_event` to the connected bot.
+///
+/// ```ignore
+/// let watcher = MockWatcher::start().await?;
+/// let connector: Connector<WebSocketWriter, WebSocketReader, String> =
+///     Connector::new::<WebSockets>(watcher.endpoint(), comms, connectivity).await?;
+///
+/// watcher.push_event(Message { event: EventType::NewJudgementRequest, data: ... });
+/// ```
+pub struct MockWatcher {
+    addr: SocketAddr,
+    events: UnboundedSender<Message>,
+    received: Arc<RwLock<Vec<Message>>>,
+}
+
+impl MockWatcher {
+    /// Binds to a random local port and starts accepting connections.
+    pub async fn start() -> Result<Self> {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        let (sender, receiver) = unbounded();
+        let events = Arc::new(Mutex::new(receiver));
+        let received = Arc::new(RwLock::new(vec![]));
+
+        let l_received = Arc::clone(&received);
+        tokio::spawn(async move {
+            loop {
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(err) => {
+                        error!("Mock Watcher failed to accept connection: {}", err);
+                        continue;
+                    }
+                };
+
+                let ws = match accept_async(stream).await {
+                    Ok(ws) => ws,
+                    Err(err) => {
+                        error!("Mock Watcher websocket handshake failed: {}", err);
+                        continue;
+                    }
+                };
+
+                // The bot only maintains a single connection, so connections
+                // are handled one after another.
+                let _ = Self::handle_connection(ws, Arc::clone(&events), Arc::clone(&l_received))
+                    .await
+                    .map_err(|err| {
+                        error!("Mock Watcher connection closed: {}", err);
+                    });
+            }
+        });
+
+        Ok(MockWatcher {
+            addr: addr,
+            events: sender,
+            received: received,
+        })
+    }
+    /// The websocket endpoint the bot should connect to.
+    pub fn endpoint(&self) -> String {
+        format!("ws://{}", self.addr)
+    }
+    /// Sends the event to the connected bot. Events are buffered until a
+    /// connection is established.
+    pub fn push_event(&self, event: Message) {
+        // Only fails if the server task has exited.
+        let _ = self.events.unbounded_send(event);
+    }
+    /// All messages received from the bot so far.
+    pub async fn received(&self) -> Vec<Message> {
+        self.received.read().await.clone()
+    }
+    /// All judgements submitted by the bot so far.
+    pub async fn judgements(&self) -> Vec<JudgementResponse> {
+        self.received
+            .read()
+            .await
+            .iter()
+            .filter(|msg| msg.event == EventType::JudgementResult)
+            .filter_map(|msg| serde_json::from_value(msg.data.clone()).ok())
+            .collect()
+    }
+    async fn handle_connection(
+        ws: WebSocketStream<TcpStream>,
+        events: Arc<Mutex<UnboundedReceiver<Message>>>,
+        received: Arc<RwLock<Vec<Message>>>,
+    ) -> Result<()> {
+        let (mut sink, mut stream) = ws.split();
+        let mut events = events.lock().await;
+
+        loop {
+            tokio::select! {
+                message = stream.next() => {
+                    let text = match message {
+                        Some(Ok(TungMessage::Text(text))) => text,
+                        Some(Ok(_)) => continue,
+                        Some(Err(err)) => return Err(err.into()),
+                        None => return Ok(()),
+                    };
+
+                    let msg = match serde_json::from_str::<Message>(&text) {
+                        Ok(msg) => msg,
+                        Err(_) => {
+                            warn!("Mock Watcher received invalid message: {}", text);
+                            continue;
+                        }
+                    };
+
+                    let response = Self::respond(&msg);
+                    received.write().await.push(msg);
+
+                    if let Some(response) = response {
+                        sink.send(TungMessage::Text(serde_json::to_string(&response)?))
+                            .await?;
+                    }
+                }
+                event = events.next() => {
+                    if let Some(event) = event {
+                        sink.send(TungMessage::Text(serde_json::to_string(&event)?))
+                            .await?;
+                    } else {
+                        // The `MockWatcher` handle was dropped.
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+    fn respond(msg: &Message) -> Option<Message> {
+        match msg.event {
+            EventType::JudgementResult => {
+                let judgement = serde_json::from_value::<JudgementResponse>(msg.data.clone())
+                    .map_err(|_| warn!("Mock Watcher received invalid judgement: {:?}", msg))
+                    .ok()?;
+
+                info!(
+                    "Mock Watcher received judgement for {}: {:?}",
+                    judgement.address, judgement.judgement
+                );
+
+                Some(Message {
+                    event: EventType::Ack,
+                    data: serde_json::to_value(&JudgementGiven {
+                        address: judgement.address,
+                        result: "judgement given".to_string(),
+                    })
+                    .unwrap(),
+                })
+            }
+            EventType::PendingJudgementsRequests => Some(Message {
+                event: EventType::PendingJudgementsResponse,
+                data: serde_json::to_value(Vec::<()>::new()).unwrap(),
+            }),
+            EventType::DisplayNamesRequest => Some(Message {
+                event: EventType::DisplayNamesResponse,
+                data: serde_json::to_value(Vec::<()>::new()).unwrap(),
+            }),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,3 @@
+mod mock_watcher;
+
+pub use mock_watcher::MockWatcher;
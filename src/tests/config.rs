@@ -0,0 +1,144 @@
+use crate::primitives::Challenge;
+use crate::{Config, ConfigError, InvalidConfig};
+use std::os::unix::fs::PermissionsExt;
+
+fn sample_config() -> Config {
+    serde_json::from_str(
+        r#"{
+            "registrar_db_path": "/tmp/registrar.db",
+            "matrix_db_path": "/tmp/matrix.db",
+            "log_level": "DEBUG",
+            "watcher_url": "ws://localhost:3001",
+            "enable_watcher": true,
+            "enable_accounts": true,
+            "enable_health_check": true,
+            "matrix_homeserver": "https://matrix.web3.foundation",
+            "matrix_username": "test-account",
+            "matrix_password": "XXXXXX",
+            "twitter_screen_name": "twitter_screen_name",
+            "twitter_api_key": "twitter_api_key",
+            "twitter_api_secret": "twitter_api_secret",
+            "twitter_token": "twitter_token",
+            "twitter_token_secret": "twitter_token_secret",
+            "imap_server": "imap.gmail.com",
+            "email_server": "smtp-relay.gmail.com",
+            "email_inbox": "INBOX",
+            "email_user": "test@test.test",
+            "email_password": "email_password"
+        }"#,
+    )
+    .unwrap()
+}
+
+#[test]
+fn config_validate() {
+    let config = sample_config();
+    assert!(config.validate().is_ok());
+
+    // All problems are reported at once.
+    let mut config = sample_config();
+    config.watcher_url = "http://localhost:3001".to_string();
+    config.matrix_homeserver = "matrix.web3.foundation".to_string();
+    config.matrix_password = "".to_string();
+    config.registrar_db_path = "/does/not/exist/registrar.db".to_string();
+    config.comms_channel_capacity = Some(0);
+    config.adapter_timeout_seconds = Some(0);
+
+    let errors = config.validate().unwrap_err();
+    assert_eq!(errors.len(), 6);
+    assert!(errors.contains(&ConfigError::Zero("comms_channel_capacity")));
+    assert!(errors.contains(&ConfigError::Zero("adapter_timeout_seconds")));
+    assert!(errors.contains(&ConfigError::EmptyField("matrix_password")));
+    assert!(errors.contains(&ConfigError::InvalidUrl(
+        "watcher_url",
+        "ws:// or wss://",
+        "http://localhost:3001".to_string()
+    )));
+    assert!(errors.contains(&ConfigError::InvalidUrl(
+        "matrix_homeserver",
+        "https://",
+        "matrix.web3.foundation".to_string()
+    )));
+    assert!(errors
+        .iter()
+        .any(|err| matches!(err, ConfigError::NotWritable("registrar_db_path", _))));
+
+    // An existing database is left untouched.
+    let mut config = sample_config();
+    config.registrar_db_path = format!("/tmp/registrar_{}.db", Challenge::gen_random().as_str());
+    std::fs::write(&config.registrar_db_path, b"content").unwrap();
+    assert!(config.validate().is_ok());
+    assert_eq!(
+        std::fs::read(&config.registrar_db_path).unwrap(),
+        b"content"
+    );
+
+    // A missing database is not created by the check.
+    let mut config = sample_config();
+    config.registrar_db_path = format!("/tmp/registrar_{}.db", Challenge::gen_random().as_str());
+    assert!(config.validate().is_ok());
+    assert!(!std::path::Path::new(&config.registrar_db_path).exists());
+
+    // The database cannot be created in a read-only directory.
+    let dir = format!("/tmp/registrar_{}", Challenge::gen_random().as_str());
+    std::fs::create_dir(&dir).unwrap();
+    std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o555)).unwrap();
+
+    let mut config = sample_config();
+    config.registrar_db_path = format!("{}/registrar.db", dir);
+    assert_eq!(
+        config.validate().unwrap_err(),
+        vec![ConfigError::NotWritable(
+            "registrar_db_path",
+            format!("{}: directory is read-only", dir)
+        )]
+    );
+
+    // Account fields are not required if accounts are disabled.
+    let mut config = sample_config();
+    config.enable_accounts = false;
+    config.matrix_homeserver = "".to_string();
+    config.email_password = "".to_string();
+    assert!(config.validate().is_ok());
+    // Freezing identities requires a token.
+    let mut config = sample_config();
+    config.enable_admin_freeze = true;
+    assert_eq!(
+        config.validate().unwrap_err(),
+        vec![ConfigError::EmptyField("admin_token")]
+    );
+
+    config.admin_token = "secret".to_string();
+    assert!(config.validate().is_ok());
+
+    // The Matrix rate limit must be positive.
+    for &rate in &[0.0, -1.0, std::f32::NAN, std::f32::INFINITY] {
+        let mut config = sample_config();
+        config.matrix_max_messages_per_second = rate;
+        assert_eq!(
+            config.validate().unwrap_err(),
+            vec![ConfigError::NotPositive(
+                "matrix_max_messages_per_second",
+                rate.to_string()
+            )]
+        );
+    }
+
+    let mut config = sample_config();
+    config.matrix_max_messages_per_second = 0.5;
+    assert!(config.validate().is_ok());
+}
+
+#[test]
+fn invalid_config_display() {
+    let err = InvalidConfig(vec![
+        ConfigError::EmptyField("matrix_password"),
+        ConfigError::Zero("adapter_timeout_seconds"),
+    ]);
+
+    assert_eq!(
+        err.to_string(),
+        "invalid config: 'matrix_password' must not be empty; \
+         'adapter_timeout_seconds' must be greater than zero"
+    );
+}
@@ -2,6 +2,7 @@ use super::mocks::*;
 use super::{db_path, pause};
 use crate::adapters::email::{EmailId, ReceivedMessageContext};
 use crate::connector::{AckResponse, EventType, JudgementRequest, Message};
+use crate::manager::AccountStatus;
 use crate::primitives::{Account, AccountType, Challenge, NetAccount};
 use crate::{test_run, Database};
 use schnorrkel::Keypair;
@@ -85,6 +86,81 @@ fn email_init_message() {
     });
 }
 
+#[test]
+fn email_malformed_pgp_fingerprint() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let db = Database::new(&db_path()).unwrap();
+        let manager = Arc::new(EventManager::new());
+        let (_, email_child) = manager.child();
+
+        let email_transport = EmailMocker::new(email_child);
+
+        let handlers = test_run(
+            Arc::clone(&manager),
+            db.clone(),
+            Default::default(),
+            DummyTransport::new(),
+            DummyTransport::new(),
+            email_transport,
+        )
+        .await
+        .unwrap();
+
+        let injector = handlers.reader.injector();
+
+        // The malformed fingerprint does not reject the entire request.
+        let msg = serde_json::to_string(&Message {
+            event: EventType::NewJudgementRequest,
+            data: serde_json::to_value(&JudgementRequest {
+                address: NetAccount::alice(),
+                accounts: [
+                    (AccountType::Email, Some(Account::from("alice@email.com"))),
+                    (
+                        AccountType::PGPFingerprint,
+                        Some(Account::from("not a fingerprint")),
+                    ),
+                ]
+                .iter()
+                .cloned()
+                .collect(),
+            })
+            .unwrap(),
+        })
+        .unwrap();
+
+        injector.send_message(msg).await;
+        pause().await;
+
+        // The user is informed about the fingerprint and receives the
+        // challenge of the email account.
+        let events = manager.events().await;
+        assert!(events.contains(&Event::Email(EmailEvent::SendMessage {
+            account: Account::from("alice@email.com"),
+            message: VerifierMessageBlank::NotifyViolation,
+        })));
+        assert!(events.iter().any(|event| match event {
+            Event::Email(EmailEvent::SendMessage { account, message }) => {
+                account == &Account::from("alice@email.com")
+                    && (message == &VerifierMessageBlank::InitMessage
+                        || message == &VerifierMessageBlank::InitMessageWithContext)
+            }
+            _ => false,
+        }));
+
+        // Marked as notified once the user was informed.
+        let statuses = db
+            .select_account_statuses(&NetAccount::alice())
+            .await
+            .unwrap();
+        assert!(statuses.contains(&(
+            AccountType::PGPFingerprint,
+            Account::from("not a fingerprint"),
+            AccountStatus::Notified
+        )));
+    });
+}
+
 #[test]
 fn email_valid_signature_response() {
     let mut rt = Runtime::new().unwrap();
@@ -179,6 +255,83 @@ fn email_valid_signature_response() {
     });
 }
 
+#[test]
+fn email_response_for_frozen_identity() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let db = Database::new(&db_path()).unwrap();
+        let manager = Arc::new(EventManager::new());
+        let (writer, email_child) = manager.child();
+
+        let email_transport = EmailMocker::new(email_child);
+
+        let handlers = test_run(
+            Arc::clone(&manager),
+            db.clone(),
+            Default::default(),
+            DummyTransport::new(),
+            DummyTransport::new(),
+            email_transport,
+        )
+        .await
+        .unwrap();
+
+        let injector = handlers.reader.injector();
+        let keypair = Keypair::generate();
+        let net_account = NetAccount::from(&keypair.public);
+
+        // Generate events.
+        let msg = serde_json::to_string(&Message {
+            event: EventType::NewJudgementRequest,
+            data: serde_json::to_value(&JudgementRequest {
+                address: net_account.clone(),
+                accounts: [(AccountType::Email, Some(Account::from("alice@email.com")))]
+                    .iter()
+                    .cloned()
+                    .collect(),
+            })
+            .unwrap(),
+        })
+        .unwrap();
+
+        // Send new judgement request.
+        injector.send_message(msg.clone()).await;
+        pause().await;
+
+        db.freeze_identity(&net_account).await.unwrap();
+
+        // Respond with valid signature.
+        let signature =
+            keypair.sign_simple(b"substrate", Challenge::gen_fixed().as_str().as_bytes());
+
+        writer
+            .send_message(ReceivedMessageContext {
+                id: EmailId::from(111u32),
+                sender: Account::from("alice@email.com"),
+                body: hex::encode(signature.to_bytes()),
+            })
+            .await;
+
+        pause().await;
+
+        // Verify events.
+        let events = manager.events().await;
+
+        assert!(events.contains(&Event::Email(EmailEvent::SendMessage {
+            account: Account::from("alice@email.com"),
+            message: VerifierMessageBlank::VerificationPaused,
+        })));
+
+        assert!(!events.contains(&Event::Email(EmailEvent::SendMessage {
+            account: Account::from("alice@email.com"),
+            message: VerifierMessageBlank::ResponseValid,
+        })));
+
+        // Nothing was stored.
+        assert!(!db.is_fully_verified(&net_account).await.unwrap());
+    });
+}
+
 #[test]
 fn email_invalid_signature_response() {
     let mut rt = Runtime::new().unwrap();
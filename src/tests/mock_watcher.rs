@@ -0,0 +1,69 @@
+use crate::comms::{CommsVerifier, Shutdown};
+use crate::connector::{AckResponse, Connector, EventType, JudgementRequest, Message};
+use crate::primitives::{Account, AccountType, NetAccount};
+use crate::testing::MockWatcher;
+use crate::{WebSocketReader, WebSocketWriter, WebSockets};
+use tokio::runtime::Runtime;
+use tokio::time::{self, Duration};
+
+#[test]
+fn connector_with_mock_watcher() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let watcher = MockWatcher::start().await.unwrap();
+
+        let connector: Connector<WebSocketWriter, WebSocketReader, String> =
+            Connector::new::<WebSockets>(
+                watcher.endpoint(),
+                CommsVerifier::new(),
+                Default::default(),
+            )
+            .await
+            .unwrap();
+
+        tokio::spawn(async move {
+            connector.start::<WebSockets>(Shutdown::default()).await;
+        });
+
+        // Inject a new judgement request.
+        watcher.push_event(Message {
+            event: EventType::NewJudgementRequest,
+            data: serde_json::to_value(&JudgementRequest {
+                address: NetAccount::alice(),
+                accounts: [(
+                    AccountType::Matrix,
+                    Some(Account::from("@alice:matrix.org")),
+                )]
+                .iter()
+                .cloned()
+                .collect(),
+            })
+            .unwrap(),
+        });
+
+        time::delay_for(Duration::from_secs(1)).await;
+
+        // Verify messages sent by the bot.
+        let received = watcher.received().await;
+
+        assert!(received.contains(&Message {
+            event: EventType::DisplayNamesRequest,
+            data: serde_json::to_value(Option::<()>::None).unwrap(),
+        }));
+
+        assert!(received.contains(&Message {
+            event: EventType::PendingJudgementsRequests,
+            data: serde_json::to_value(Option::<()>::None).unwrap(),
+        }));
+
+        assert!(received.contains(&Message {
+            event: EventType::Ack,
+            data: serde_json::to_value(&AckResponse {
+                result: "Message acknowledged".to_string(),
+            })
+            .unwrap(),
+        }));
+
+        assert!(watcher.judgements().await.is_empty());
+    });
+}
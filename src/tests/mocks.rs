@@ -32,6 +32,7 @@ pub enum VerifierMessageBlank {
     NotifyViolation,
     InvalidFormat,
     Goodbye,
+    VerificationPaused,
 }
 
 impl From<VerifierMessage> for VerifierMessageBlank {
@@ -45,6 +46,7 @@ impl From<VerifierMessage> for VerifierMessageBlank {
             VerifierMessage::NotifyViolation(_) => VerifierMessageBlank::NotifyViolation,
             VerifierMessage::InvalidFormat(_) => VerifierMessageBlank::InvalidFormat,
             VerifierMessage::Goodbye(_) => VerifierMessageBlank::Goodbye,
+            VerifierMessage::VerificationPaused(_) => VerifierMessageBlank::VerificationPaused,
         }
     }
 }
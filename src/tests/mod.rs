@@ -1,9 +1,12 @@
 use crate::primitives::Challenge;
 use tokio::time::{self, Duration};
 
+mod config;
 mod email_adapter;
 mod matrix_adapter;
+mod mock_watcher;
 pub mod mocks;
+mod shutdown;
 mod twitter_adapter;
 
 // Generate a random db path
@@ -0,0 +1,48 @@
+use crate::Tasks;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::runtime::Runtime;
+use tokio::time::{self, Duration};
+
+#[test]
+fn stop_tasks() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let mut tasks = Tasks::default();
+        let finished = Arc::new(AtomicBool::new(false));
+
+        // Finishes its current work after the signal.
+        let mut shutdown = tasks.shutdown().subscribe();
+        let l_finished = Arc::clone(&finished);
+        tasks.spawn(async move {
+            shutdown.recv().await;
+            time::delay_for(Duration::from_millis(100)).await;
+            l_finished.store(true, Ordering::SeqCst);
+        });
+
+        // Subscribes after the signal was sent.
+        let shutdown = tasks.shutdown();
+        tasks.spawn(async move {
+            time::delay_for(Duration::from_millis(100)).await;
+            shutdown.subscribe().recv().await;
+        });
+
+        assert!(tasks.stop(Duration::from_secs(5)).await);
+        assert!(finished.load(Ordering::SeqCst));
+    });
+}
+
+#[test]
+fn stop_tasks_timeout() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let mut tasks = Tasks::default();
+
+        // Ignores the signal.
+        tasks.spawn(async {
+            time::delay_for(Duration::from_secs(60)).await;
+        });
+
+        assert!(!tasks.stop(Duration::from_millis(100)).await);
+    });
+}
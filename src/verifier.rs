@@ -1,8 +1,9 @@
 use crate::adapters::VIOLATIONS_CAP;
 use crate::comms::CommsVerifier;
-use crate::manager::AccountStatus;
+use crate::manager::{AccountStatus, OnChainIdentity};
 use crate::primitives::{
-    Account, AccountType, Challenge, ChallengeStatus, NetworkAddress, Result, Signature,
+    Account, AccountType, AddressFormat, Challenge, ChallengeStatus, NetAccount, NetworkAddress,
+    Result, Signature,
 };
 use crate::Database;
 use schnorrkel::sign::Signature as SchnorrkelSignature;
@@ -14,10 +15,33 @@ const INTRODUCTION_STR: &'static str = "\
     the issuer has requested the Web3 Registrar service to judge this account. \
     If you did not issue this request then just ignore this message.\n\n";
 
+const POLKADOT_JS_APPS_URL: &'static str = "https://polkadot.js.org/apps/";
+
+/// Creates a link to the signing page of Polkadot JS Apps, connected to the
+/// network of the given address.
+pub fn apps_signing_link(network_address: &NetworkAddress) -> String {
+    let rpc = match network_address.address_format() {
+        AddressFormat::Polkadot => Some("wss://rpc.polkadot.io"),
+        AddressFormat::Kusama => Some("wss://kusama-rpc.polkadot.io"),
+        AddressFormat::Generic => None,
+    };
+
+    match rpc {
+        Some(rpc) => format!(
+            "{}?rpc={}#/signing",
+            POLKADOT_JS_APPS_URL,
+            urlencoding::encode(rpc)
+        ),
+        None => format!("{}#/signing", POLKADOT_JS_APPS_URL),
+    }
+}
+
 #[derive(Debug, Fail)]
 pub enum VerifierError {
     #[fail(display = "This is not a valid signature output.")]
     InvalidSignature,
+    #[fail(display = "verification of identity {} is frozen", 0)]
+    FrozenIdentity(NetAccount),
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -29,6 +53,7 @@ pub enum VerifierMessage {
     NotifyViolation(String),
     InvalidFormat(String),
     Goodbye(String),
+    VerificationPaused(String),
 }
 
 impl VerifierMessage {
@@ -44,6 +69,7 @@ impl VerifierMessage {
             NotifyViolation(msg) => &msg,
             InvalidFormat(msg) => &msg,
             Goodbye(msg) => &msg,
+            VerificationPaused(msg) => &msg,
         }
     }
 }
@@ -61,6 +87,7 @@ impl fmt::Display for VerifierMessage {
             NotifyViolation(msg) => write!(f, "{}", msg),
             InvalidFormat(msg) => write!(f, "{}", msg),
             Goodbye(msg) => write!(f, "{}", msg),
+            VerificationPaused(msg) => write!(f, "{}", msg),
         }
     }
 }
@@ -139,6 +166,8 @@ impl<'a> Verifier<'a> {
             message.push_str(&format!("> {}", network_address.address().as_str()));
             message.push_str("\nCHALLENGE:\n");
             message.push_str(&format!("> {}", challenge.as_str()));
+            message.push_str("\nSIGN WITH POLKADOT JS APPS:\n");
+            message.push_str(&format!("> {}", apps_signing_link(network_address)));
         }
 
         message.push_str(
@@ -153,7 +182,7 @@ impl<'a> Verifier<'a> {
             VerifierMessage::InitMessage(message)
         }
     }
-    pub fn response_message_builder(&self) -> VerifierMessage {
+    pub fn response_message_builder(&self, idents: &[OnChainIdentity]) -> VerifierMessage {
         let mut message = String::new();
 
         if self.valid.is_empty() {
@@ -181,16 +210,42 @@ impl<'a> Verifier<'a> {
             }
         }
 
+        for ident in idents {
+            message.push_str("\n\n");
+            message.push_str(&ident.to_user_message());
+        }
+
         VerifierMessage::ResponseValid(message)
     }
 }
 
+/// Updates the challenge statuses and notifies the manager. Returns the
+/// current state of the identities whose challenge was accepted.
+///
+/// Returns `VerifierError::FrozenIdentity` without changing anything if any of
+/// the involved identities was frozen by an operator.
 pub async fn verification_handler<'a>(
     verifier: &Verifier<'a>,
     db: &Database,
     comms: &CommsVerifier,
     account_ty: &AccountType,
-) -> Result<()> {
+) -> Result<Vec<OnChainIdentity>> {
+    for network_address in verifier
+        .valid_verifications()
+        .into_iter()
+        .chain(verifier.invalid_verifications())
+    {
+        if db.is_frozen(network_address.address()).await? {
+            warn!(
+                "Ignoring response for frozen identity: {}",
+                network_address.address()
+            );
+            return Err(VerifierError::FrozenIdentity(network_address.address().clone()).into());
+        }
+    }
+
+    let mut idents = vec![];
+
     for network_address in verifier.valid_verifications() {
         debug!(
             "Valid verification for address: {}",
@@ -204,6 +259,10 @@ pub async fn verification_handler<'a>(
         )
         .await?;
 
+        if let Some(ident) = db.select_identity(network_address.address()).await? {
+            idents.push(ident);
+        }
+
         comms.notify_status_change(network_address.address().clone());
     }
 
@@ -223,7 +282,29 @@ pub async fn verification_handler<'a>(
         comms.notify_status_change(network_address.address().clone());
     }
 
-    Ok(())
+    Ok(idents)
+}
+
+/// Runs the `verification_handler` and builds the response for the user. If
+/// the identity is frozen, the user is told that the verification is paused.
+pub async fn verification_response<'a>(
+    verifier: &Verifier<'a>,
+    db: &Database,
+    comms: &CommsVerifier,
+    account_ty: &AccountType,
+) -> Result<VerifierMessage> {
+    match verification_handler(verifier, db, comms, account_ty).await {
+        Ok(idents) => Ok(verifier.response_message_builder(&idents)),
+        Err(err) => match err.downcast::<VerifierError>() {
+            Ok(VerifierError::FrozenIdentity(_)) => Ok(VerifierMessage::VerificationPaused(
+                "The verification of this identity is currently paused by the registrar. \
+                Your response was not processed, please try again later."
+                    .to_string(),
+            )),
+            Ok(err) => Err(err.into()),
+            Err(err) => Err(err),
+        },
+    }
 }
 
 pub fn invalid_accounts_message(
@@ -277,6 +358,12 @@ pub fn invalid_accounts_message(
                 account_ty.to_string(),
                 account.as_str(),
             ));
+        } else if account_ty == &AccountType::PGPFingerprint {
+            message.push_str(&format!(
+                "* \"{}\" ({}) is not a valid fingerprint.\n",
+                account.as_str(),
+                account_ty.to_string()
+            ));
         } else {
             message.push_str(&format!(
                 "* \"{}\" ({}) could not be reached.\n",
@@ -298,6 +385,29 @@ pub fn invalid_accounts_message(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::primitives::NetAccount;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn apps_signing_link_for_network() {
+        let polkadot = NetworkAddress::try_from(NetAccount::from(
+            "14GcE3qBiEnAyg2sDfadT3fQhWd2Z3M59tWi1CvVV8UwxUfU",
+        ))
+        .unwrap();
+        assert_eq!(
+            apps_signing_link(&polkadot),
+            "https://polkadot.js.org/apps/?rpc=wss%3A%2F%2Frpc.polkadot.io#/signing"
+        );
+
+        let kusama = NetworkAddress::try_from(NetAccount::from(
+            "HNZata7iMYWmk5RvZRTiAsSDhV8366zq2YGb3tLH5Upf74F",
+        ))
+        .unwrap();
+        assert_eq!(
+            apps_signing_link(&kusama),
+            "https://polkadot.js.org/apps/?rpc=wss%3A%2F%2Fkusama-rpc.polkadot.io#/signing"
+        );
+    }
 
     #[test]
     fn invalid_accounts_message_status_invalid() {